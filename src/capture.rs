@@ -0,0 +1,271 @@
+//! Packet capture for `--pcap`, with an `AF_PACKET` socket instead of libpcap.
+//!
+//! Packets are written as raw IP (no link-layer header), only the TCP segments
+//! to or from the experiment port are kept.
+
+use failure::ResultExt;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+
+use crate::sock_diag::canonical;
+
+/// bytes captured per packet, a whole IP packet
+const SNAPLEN: usize = 65535;
+/// `LINKTYPE_RAW`, packets start with the IPv4 or IPv6 header
+const LINKTYPE_RAW: u32 = 101;
+/// time the capture thread gets to see a connection's last segments before `log_timeline`
+const SETTLE: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// A segment with SYN, FIN or RST set, or advertising a zero window.
+struct Event {
+    at: std::time::SystemTime,
+    src: SocketAddr,
+    dst: SocketAddr,
+    what: String,
+}
+
+/// events not yet logged by `log_timeline`, set once the capture runs
+static EVENTS: OnceLock<Mutex<Vec<Event>>> = OnceLock::new();
+
+/// capture the segments to and from `port` into a new pcap file at `path`, on a background thread
+pub fn start(path: &std::path::Path, port: u16) -> Result<(), failure::Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let socket = open_socket().context("open AF_PACKET socket (needs CAP_NET_RAW)")?;
+        let mut out = io::BufWriter::new(
+            std::fs::File::create(path).with_context(|_| format!("create pcap file {:?}", path))?,
+        );
+        write_header(&mut out).context("write pcap header")?;
+        if EVENTS.set(Mutex::new(Vec::new())).is_err() {
+            failure::bail!("packet capture already running");
+        }
+        std::thread::spawn(move || {
+            if let Err(e) = capture(&socket, &mut out, port) {
+                log::error!("packet capture failed: {:?}", e);
+            }
+        });
+        log::info!("capturing port {} into {:?}", port, path);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, port);
+        failure::bail!("--pcap is Linux only")
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_socket() -> io::Result<std::os::unix::io::OwnedFd> {
+    use std::os::unix::io::FromRawFd;
+
+    let protocol = (libc::ETH_P_ALL as u16).to_be();
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::c_int::from(protocol),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { std::os::unix::io::OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(target_os = "linux")]
+fn capture(socket: &std::os::unix::io::OwnedFd, out: &mut impl Write, port: u16) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let loopback =
+        unsafe { libc::if_nametoindex(b"lo\0".as_ptr() as *const libc::c_char) } as libc::c_int;
+    let mut buf = vec![0_u8; SNAPLEN];
+    loop {
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        let mut addr_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        // with MSG_TRUNC, returns the packet's full length even if it didn't fit
+        let len = unsafe {
+            libc::recvfrom(
+                socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_TRUNC,
+                &mut addr as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut addr_len,
+            )
+        };
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        let at = std::time::SystemTime::now();
+        // loopback packets show up twice, leaving and arriving
+        if addr.sll_ifindex == loopback && addr.sll_pkttype == libc::PACKET_OUTGOING {
+            continue;
+        }
+        let orig_len = len as usize;
+        let packet = &buf[..orig_len.min(buf.len())];
+        let segment = match Segment::parse(packet) {
+            Some(segment) if segment.src.port() == port || segment.dst.port() == port => segment,
+            _ => continue,
+        };
+        write_record(out, at, packet, orig_len)?;
+        out.flush()?;
+        if let Some(what) = segment.event() {
+            let event = Event {
+                at,
+                src: segment.src,
+                dst: segment.dst,
+                what,
+            };
+            EVENTS
+                .get()
+                .expect("set by start")
+                .lock()
+                .unwrap()
+                .push(event);
+        }
+    }
+}
+
+fn write_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&0xa1b2_c3d4_u32.to_ne_bytes())?;
+    out.write_all(&2_u16.to_ne_bytes())?;
+    out.write_all(&4_u16.to_ne_bytes())?;
+    // time zone offset and timestamp accuracy, always zero
+    out.write_all(&[0; 8])?;
+    out.write_all(&(SNAPLEN as u32).to_ne_bytes())?;
+    out.write_all(&LINKTYPE_RAW.to_ne_bytes())
+}
+
+fn write_record(
+    out: &mut impl Write,
+    at: std::time::SystemTime,
+    packet: &[u8],
+    orig_len: usize,
+) -> io::Result<()> {
+    let since_epoch = at.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    out.write_all(&(since_epoch.as_secs() as u32).to_ne_bytes())?;
+    out.write_all(&since_epoch.subsec_micros().to_ne_bytes())?;
+    out.write_all(&(packet.len() as u32).to_ne_bytes())?;
+    out.write_all(&(orig_len as u32).to_ne_bytes())?;
+    out.write_all(packet)
+}
+
+/// The parts of a TCP segment the timeline needs.
+struct Segment {
+    src: SocketAddr,
+    dst: SocketAddr,
+    flags: u8,
+    window: u16,
+}
+
+impl Segment {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const ACK: u8 = 0x10;
+
+    /// a TCP segment in an IPv4 or IPv6 packet without extension headers
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let (src, dst, tcp) = match packet.first()? >> 4 {
+            4 => {
+                let header_len = usize::from(packet[0] & 0x0f) * 4;
+                if packet.len() < header_len.max(20) || packet[9] != libc::IPPROTO_TCP as u8 {
+                    return None;
+                }
+                let ip = |at: usize| {
+                    IpAddr::from([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]])
+                };
+                (ip(12), ip(16), &packet[header_len..])
+            }
+            6 => {
+                if packet.len() < 40 || packet[6] != libc::IPPROTO_TCP as u8 {
+                    return None;
+                }
+                let ip = |at: usize| {
+                    let mut octets = [0_u8; 16];
+                    octets.copy_from_slice(&packet[at..at + 16]);
+                    IpAddr::from(octets)
+                };
+                (ip(8), ip(24), &packet[40..])
+            }
+            _ => return None,
+        };
+        if tcp.len() < 20 {
+            return None;
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([tcp[at], tcp[at + 1]]);
+        Some(Segment {
+            src: SocketAddr::new(src, u16_at(0)),
+            dst: SocketAddr::new(dst, u16_at(2)),
+            flags: tcp[13],
+            window: u16_at(14),
+        })
+    }
+
+    /// what makes this segment part of the timeline, if anything
+    fn event(&self) -> Option<String> {
+        let mut what = Vec::new();
+        if self.flags & Self::SYN != 0 {
+            what.push(if self.flags & Self::ACK != 0 {
+                "SYN-ACK"
+            } else {
+                "SYN"
+            });
+        }
+        if self.flags & Self::FIN != 0 {
+            what.push("FIN");
+        }
+        if self.flags & Self::RST != 0 {
+            what.push("RST");
+        }
+        if self.window == 0 && self.flags & (Self::SYN | Self::RST) == 0 {
+            what.push("zero window");
+        }
+        if what.is_empty() {
+            None
+        } else {
+            Some(what.join(" "))
+        }
+    }
+}
+
+/// log the timeline of the connection between `local` and `peer`, if capturing
+///
+/// Waits briefly for the capture thread to catch up first. Later segments,
+/// like the peer's reaction to our close, may be missing.
+pub fn log_timeline(local: Option<SocketAddr>, peer: Option<SocketAddr>) {
+    let (events, local, peer) = match (EVENTS.get(), local, peer) {
+        (Some(events), Some(local), Some(peer)) => (events, canonical(local), canonical(peer)),
+        _ => return,
+    };
+    std::thread::sleep(SETTLE);
+    let (conn_events, rest): (Vec<_>, Vec<_>) = events
+        .lock()
+        .unwrap()
+        .drain(..)
+        .partition(|e| (e.src == local && e.dst == peer) || (e.src == peer && e.dst == local));
+    events.lock().unwrap().extend(rest);
+    let start = match conn_events.first() {
+        Some(first) => first.at,
+        None => {
+            log::info!("packet timeline: no SYN, FIN, RST or zero window captured");
+            return;
+        }
+    };
+    let mut timeline = String::new();
+    for e in &conn_events {
+        let offset = e.at.duration_since(start).unwrap_or_default();
+        let direction = if e.src == local {
+            "local -> peer"
+        } else {
+            "peer -> local"
+        };
+        timeline += &format!("\n  +{:.6}s {} {}", offset.as_secs_f64(), direction, e.what);
+    }
+    log::info!("packet timeline:{}", timeline);
+}
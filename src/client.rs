@@ -0,0 +1,2953 @@
+//! The client: runs the request protocol against a server and classifies
+//! how each run ended.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, prelude::*, BufWriter};
+use std::net::{self, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::{
+    atomic::{self, AtomicBool, AtomicUsize},
+    Arc, Mutex,
+};
+
+use failure::ResultExt;
+use rand::{RngExt, SeedableRng};
+use structopt::StructOpt;
+
+use crate::stats::{ClientStats, ErrorEvent};
+#[cfg(feature = "tls")]
+use crate::tls;
+#[cfg(feature = "tokio")]
+use crate::tokio_runtime;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+use crate::{
+    capture, close_conn, dump_sockopts, log_tcp_info, set_buffer_sizes, set_user_timeout,
+    setsockopt_int, sock_diag, tcp_socket, tcp_state_name, unix_addr, Backend, Compress,
+    ConnLogSpan, Endian, Framing, Keepalive, SockOpt, SpinOnWouldBlock, StreamSocket, TeardownMode,
+    Transport, WordSize,
+};
+
+/// Position of the odd number in the request stream, see `--odd-after`.
+#[derive(Clone, Copy)]
+enum OddAfter {
+    /// `N`, after this many messages
+    Messages(u32),
+    /// `NB`, after this many bytes of (uncompressed) numbers
+    Bytes(u64),
+}
+
+impl std::str::FromStr for OddAfter {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, bytes) = match s.strip_suffix('B') {
+            Some(num) => (num, true),
+            None => (s, false),
+        };
+        let invalid = |_| failure::format_err!("expected `N` messages or `NB` bytes, got {:?}", s);
+        Ok(if bytes {
+            OddAfter::Bytes(num.parse().map_err(invalid)?)
+        } else {
+            OddAfter::Messages(num.parse().map_err(invalid)?)
+        })
+    }
+}
+
+impl std::fmt::Display for OddAfter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OddAfter::Messages(n) => write!(f, "{}", n),
+            OddAfter::Bytes(n) => write!(f, "{}B", n),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Client {
+    #[structopt(help = "SERVER_IP:SERVER_PORT, `[IPv6]:PORT` for IPv6")]
+    server: String,
+    #[structopt(long = "bind", help = "bind connecting socket to address IP:port")]
+    bind: Option<String>,
+    #[structopt(
+        short = "4",
+        long = "ipv4",
+        help = "connect to an IPv4 address of the server, by default the family of --bind or the first resolved address",
+        raw(conflicts_with = r#""ipv6""#)
+    )]
+    ipv4: bool,
+    #[structopt(
+        short = "6",
+        long = "ipv6",
+        help = "connect to an IPv6 address of the server"
+    )]
+    ipv6: bool,
+    #[structopt(long = "times", default_value = "1")]
+    pub times: usize,
+    #[structopt(
+        long = "concurrency",
+        help = "spread the --times runs over this many threads, each with its own connections",
+        default_value = "1"
+    )]
+    concurrency: usize,
+    #[structopt(
+        long = "send-count",
+        help = "maximum number of numbers to send per run, the odd one is sent halfway unless --odd-first or --odd-after",
+        default_value = "8388608",
+        raw(visible_alias = r#""total""#)
+    )]
+    pub send_count: u32,
+    #[structopt(
+        long = "odd-first",
+        help = "send the odd number as the very first message instead of halfway"
+    )]
+    odd_first: bool,
+    #[structopt(
+        long = "odd-after",
+        help = "send the odd number after this many messages, or after `NB` bytes of numbers",
+        raw(conflicts_with = r#""odd_first""#)
+    )]
+    odd_after: Option<OddAfter>,
+    #[structopt(
+        long = "framing",
+        help = "message framing, must match the server (`numbers` or `length-prefixed`)",
+        default_value = "numbers"
+    )]
+    framing: Framing,
+    #[structopt(
+        long = "word-size",
+        help = "bytes per number and length prefix, must match the server (`4` or `8`)",
+        default_value = "4"
+    )]
+    word_size: WordSize,
+    #[structopt(flatten)]
+    keepalive: Keepalive,
+    #[structopt(
+        long = "tcp-user-timeout",
+        help = "drop the connection once sent data stays unacknowledged this long, including queued teardown data (TCP_USER_TIMEOUT, Linux only)"
+    )]
+    tcp_user_timeout: Option<humantime::Duration>,
+    #[structopt(
+        long = "nodelay",
+        help = "disable Nagle's algorithm (TCP_NODELAY), so small writes go out without waiting for ACKs"
+    )]
+    nodelay: bool,
+    #[structopt(
+        long = "sndbuf",
+        help = "send buffer size in bytes (SO_SNDBUF), small buffers fill up after fewer numbers"
+    )]
+    sndbuf: Option<u32>,
+    #[structopt(
+        long = "rcvbuf",
+        help = "receive buffer size in bytes (SO_RCVBUF), small buffers leave unread data at close after fewer numbers"
+    )]
+    rcvbuf: Option<u32>,
+    #[structopt(
+        long = "linger",
+        help = "enable lingering for the connection (SO_LINGER, e.g. `2s`), `0s` makes every close send an RST"
+    )]
+    linger: Option<humantime::Duration>,
+    #[structopt(
+        long = "sockopt",
+        help = "set a raw integer socket option before connecting, e.g. `IP_TOS=0x10`, or `LEVEL:OPTNAME=VALUE` in numbers for options without a name here (repeatable, Unix only)",
+        raw(number_of_values = "1")
+    )]
+    sockopts: Vec<SockOpt>,
+    #[structopt(
+        long = "compress",
+        help = "compress the request stream, must match the server (`none` or `gzip`)",
+        default_value = "none"
+    )]
+    compress: Compress,
+    #[structopt(
+        long = "endian",
+        help = "byte order of the numbers on the wire, must match the peer (`big` or `little`)",
+        default_value = "big"
+    )]
+    endian: Endian,
+    #[structopt(
+        long = "repeat-forever",
+        help = "ignore --times and run until interrupted by Ctrl-C"
+    )]
+    repeat_forever: bool,
+    #[structopt(
+        long = "stats-interval",
+        help = "how often to print the stats with --repeat-forever",
+        default_value = "10s"
+    )]
+    stats_interval: humantime::Duration,
+    #[structopt(
+        long = "payload-file",
+        help = "send the raw contents of this file as the number stream",
+        parse(from_os_str)
+    )]
+    payload_file: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "summary-out",
+        help = "write the final stats to this file instead of stdout",
+        parse(from_os_str),
+        raw(visible_alias = r#""output-file""#)
+    )]
+    summary_out: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "csv",
+        help = "write the rows of --output csv to this file too",
+        parse(from_os_str)
+    )]
+    csv: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "hgrm-out",
+        help = "write the latency histograms' percentile distributions to `PREFIX.NAME.hgrm` files, in milliseconds",
+        parse(from_os_str)
+    )]
+    hgrm_out: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "pcap",
+        help = "capture the packets to and from the server port into this pcap file and log each run's SYN/FIN/RST/zero-window timeline (Linux only, needs CAP_NET_RAW)",
+        parse(from_os_str)
+    )]
+    pcap: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "retry-request",
+        help = "reconnect up to this many times until a request gets a correct response",
+        default_value = "0"
+    )]
+    retry_request: usize,
+    #[structopt(
+        long = "nonblocking",
+        help = "put the connection into nonblocking mode, spinning on WouldBlock"
+    )]
+    nonblocking: bool,
+    #[structopt(
+        long = "no-reuse-port",
+        help = "do not set SO_REUSEPORT on the connecting socket"
+    )]
+    no_reuse_port: bool,
+    #[structopt(
+        long = "single-thread",
+        help = "send and read the response on one thread using a nonblocking socket and poll"
+    )]
+    single_thread: bool,
+    #[structopt(
+        long = "syn-retries",
+        help = "number of SYN retransmits before connect gives up (TCP_SYNCNT, Linux only)"
+    )]
+    syn_retries: Option<u8>,
+    #[structopt(
+        long = "max-retries",
+        help = "number of data retransmits before the connection is dropped (TCP_RETRIES2, Linux only)"
+    )]
+    max_retries: Option<u8>,
+    #[structopt(
+        long = "responses",
+        help = "number of responses the reader thread reads per connection",
+        default_value = "1"
+    )]
+    responses: u32,
+    #[structopt(
+        long = "response-channel-capacity",
+        help = "capacity of the channel between the reader thread and the sender",
+        default_value = "16"
+    )]
+    response_channel_capacity: usize,
+    #[structopt(
+        long = "start-at",
+        help = "wait until this RFC 3339 timestamp before the first request",
+        parse(try_from_str = "humantime::parse_rfc3339_weak")
+    )]
+    start_at: Option<std::time::SystemTime>,
+    #[structopt(
+        long = "output",
+        help = "stats format, `pretty`, `csv` for one row per run followed by the stats as comments, or `json` for a document with the parameters, stats and every run (not with --repeat-forever)",
+        default_value = "pretty"
+    )]
+    output: Output,
+    #[structopt(
+        long = "dump-sockopts",
+        help = "log the effective socket options of the connection once it is set up"
+    )]
+    dump_sockopts: bool,
+    #[structopt(
+        long = "batch-size",
+        help = "assemble this many numbers into a buffer and hand each buffer to the kernel in one write",
+        default_value = "1"
+    )]
+    batch_size: u32,
+    #[structopt(
+        long = "send-after-response",
+        help = "keep sending the rest of the number stream after the response arrived"
+    )]
+    send_after_response: bool,
+    #[structopt(
+        long = "read-timeout",
+        help = "give up reading a response after this long; a response reader still blocked a grace period later is abandoned",
+        default_value = "60s"
+    )]
+    read_timeout: humantime::Duration,
+    #[structopt(
+        long = "connect-timeout",
+        help = "give up connecting after this long instead of when the kernel's SYN retries run out"
+    )]
+    connect_timeout: Option<humantime::Duration>,
+    #[structopt(
+        long = "write-timeout",
+        help = "give up a write blocked on a full send buffer after this long, e.g. when the server stopped reading"
+    )]
+    write_timeout: Option<humantime::Duration>,
+    #[structopt(
+        long = "preconnect",
+        help = "open this many connections before sending on any of them, then run the exchange on each in turn"
+    )]
+    preconnect: Option<usize>,
+    #[structopt(
+        long = "error-timeline",
+        help = "record when each failed run happened and print the gaps between consecutive failures"
+    )]
+    error_timeline: bool,
+    #[structopt(
+        long = "passive-close",
+        help = "after the response, wait for the server's FIN before closing, so the server ends up in TIME_WAIT"
+    )]
+    passive_close: bool,
+    #[structopt(
+        long = "classify-teardown",
+        help = "after the response, read until the server's FIN or RST and count which one ended the connection and when"
+    )]
+    classify_teardown: bool,
+    #[structopt(
+        long = "count-by-phase",
+        help = "count in which protocol phase each failed run first noticed the teardown"
+    )]
+    count_by_phase: bool,
+    #[structopt(
+        long = "fuzz",
+        help = "send malformed request streams (garbage, truncated numbers) and count how the server reacts"
+    )]
+    fuzz: bool,
+    #[structopt(
+        long = "seed",
+        help = "seed for random choices like --fuzz input, for reproducible experiments"
+    )]
+    seed: Option<u64>,
+    #[structopt(
+        long = "verify-sequence",
+        help = "send the numbers 0, 2, 4, ... (the odd one in place) and summarize by teardown mode how many the server proved it received; the server needs --verify-sequence too"
+    )]
+    verify_sequence: bool,
+    #[structopt(
+        long = "teardown-mode",
+        help = "how the client tears down each connection after the run, use `modes` subcommand to list modes",
+        default_value = "close-immediately"
+    )]
+    teardown_mode: TeardownMode,
+    #[structopt(
+        long = "teardown-sleep",
+        help = "time to sleep for the sleep-then-close --teardown-mode",
+        default_value = "5ms"
+    )]
+    teardown_sleep: humantime::Duration,
+    #[structopt(
+        long = "tcp-info",
+        help = "log the connection's TCP_INFO (state, retransmits, RTT, queues) before and after the --teardown-mode action (Linux only)"
+    )]
+    tcp_info: bool,
+    #[structopt(
+        long = "report-socket-states",
+        help = "after each run, look up both ends of the connection with netlink sock_diag and count their states (TIME_WAIT, FIN_WAIT2, ...), and count TIME_WAIT sockets after the batch (Linux only)"
+    )]
+    report_socket_states: bool,
+    #[structopt(
+        long = "backend",
+        help = "`std` or `tokio`, with tokio the number stream is written and the response read in tasks",
+        default_value = "std"
+    )]
+    backend: Backend,
+    #[structopt(
+        long = "io-uring",
+        help = "send the number stream and close the connection through io_uring (requires the io-uring cargo feature)"
+    )]
+    io_uring: bool,
+    #[structopt(
+        long = "transport",
+        help = "`tcp` or `unix`, with unix the server address is a socket path (or `@name` for Linux's abstract namespace)",
+        default_value = "tcp"
+    )]
+    transport: Transport,
+    #[structopt(
+        long = "tls",
+        help = "speak TLS without verifying the server's certificate, write the whole number stream before reading the response, and count how the server ended the connection: close_notify, truncation or reset"
+    )]
+    tls: bool,
+}
+
+/// Format of the client's stats output.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Output {
+    /// pretty-printed debug representation
+    Pretty,
+    /// one CSV row per run, stats as `#` comments
+    Csv,
+    /// one JSON document per stats print, with the parameters and every run
+    Json,
+}
+
+/// The client's `--csv` file, one `Client::csv_row` per run.
+pub struct RunLog {
+    out: Option<Mutex<csv::Writer<std::fs::File>>>,
+    /// runs recorded so far, across `--concurrency` threads
+    runs: AtomicUsize,
+}
+
+impl RunLog {
+    /// create a run log writing to `path`, or discarding rows if `None`
+    pub fn open(path: Option<&std::path::Path>) -> Result<Self, failure::Error> {
+        let out = match path {
+            Some(path) => {
+                let mut out = csv::Writer::from_path(path)
+                    .with_context(|_| format!("create CSV file {:?}", path))?;
+                out.write_record(Client::CSV_COLUMNS)
+                    .and_then(|()| out.flush().map_err(Into::into))
+                    .context("write CSV header")?;
+                Some(Mutex::new(out))
+            }
+            None => None,
+        };
+        Ok(RunLog {
+            out,
+            runs: AtomicUsize::new(0),
+        })
+    }
+
+    /// number the run across `--concurrency` threads and write its row, returning the number
+    fn record(&self, record: &RunRecord) -> usize {
+        // number under the lock, so the file's rows stay in order
+        let out = self.out.as_ref().map(|out| out.lock().unwrap());
+        let run = self.runs.fetch_add(1, atomic::Ordering::SeqCst);
+        if let Some(mut out) = out {
+            // flush every row, so the file is complete whenever the client stops
+            let res = out
+                .write_record(Client::csv_row(run, record))
+                .and_then(|()| out.flush().map_err(Into::into));
+            if let Err(e) = res {
+                log::error!("cannot write CSV row: {:?}", e);
+            }
+        }
+        run
+    }
+}
+
+/// How the server ended a `--tls` connection after the response.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub enum TlsEof {
+    /// close_notify, a clean TLS EOF
+    CloseNotify,
+    /// TCP FIN without close_notify
+    Truncated,
+    Reset,
+    /// neither EOF nor error within `--read-timeout`
+    Hang,
+    OtherError(io::ErrorKind),
+}
+
+/// How the server ended the connection after the response, see `--classify-teardown`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PeerTeardown {
+    /// EOF, a graceful close
+    Fin,
+    Reset,
+    /// neither EOF nor error within `--read-timeout`
+    Hang,
+    OtherError(io::ErrorKind),
+}
+
+/// How the server reacted to a malformed request stream, see `--fuzz`.
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+enum FuzzOutcome {
+    /// EOF without any response bytes
+    CleanClose,
+    /// some response bytes, then EOF
+    ResponseThenClose,
+    Reset,
+    /// neither EOF nor error within `--read-timeout`
+    Hang,
+    OtherError(io::ErrorKind),
+}
+
+/// Protocol phase a client run was in when it first noticed a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Connect,
+    /// sending the even numbers before the odd one
+    EvenSend,
+    /// handing the odd number to the kernel
+    OddSend,
+    /// waiting for or reading the response, after the odd number was sent
+    ResponseRead,
+}
+
+/// How a single run of the request protocol ended, as the client saw it.
+#[derive(Debug, Display, Hash, PartialEq, Eq, PartialOrd)]
+pub enum SingleRunResult {
+    ResponseCorrect,
+    ResponseMismatch,
+    ConnectError(RunError),
+    /// the handshake did not complete within `--connect-timeout` or the SYN retries
+    ConnectTimeout,
+    ReadResponseError(RunError),
+    /// no response within `--read-timeout`
+    ReadTimeout,
+    WriteNumberError(RunError),
+    /// a write blocked for `--write-timeout`
+    WriteTimeout,
+    /// with `--send-after-response`, writing failed after the correct response arrived
+    PostResponseWriteError(RunError),
+    /// the response reader thread did not finish within `--read-timeout` plus a grace period
+    ReaderStuck,
+    BothErr {
+        read: RunError,
+        write: RunError,
+        order: ErrorOrder,
+    },
+}
+
+/// An error a run observed, with the OS error code behind it if there was one.
+///
+/// The `io::ErrorKind` alone conflates failure modes, e.g. an `Other` may be
+/// any errno std has no kind for.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd)]
+pub struct RunError {
+    kind: io::ErrorKind,
+    errno: Option<i32>,
+}
+
+impl From<&io::Error> for RunError {
+    fn from(e: &io::Error) -> Self {
+        RunError {
+            kind: e.kind(),
+            errno: e.raw_os_error(),
+        }
+    }
+}
+
+impl RunError {
+    pub fn kind(self) -> io::ErrorKind {
+        self.kind
+    }
+
+    /// the OS error code, `None` for errors that did not come from the OS
+    pub fn errno(self) -> Option<i32> {
+        self.errno
+    }
+
+    /// a timeout we detected ourselves, there is no OS error behind it
+    fn timed_out() -> Self {
+        RunError {
+            kind: io::ErrorKind::TimedOut,
+            errno: None,
+        }
+    }
+
+    /// the symbolic name of the OS error, e.g. `ECONNRESET`
+    pub fn errno_name(self) -> Option<&'static str> {
+        #[cfg(unix)]
+        {
+            let name = match self.errno? {
+                libc::EPIPE => "EPIPE",
+                libc::ECONNRESET => "ECONNRESET",
+                libc::ECONNREFUSED => "ECONNREFUSED",
+                libc::ECONNABORTED => "ECONNABORTED",
+                libc::ETIMEDOUT => "ETIMEDOUT",
+                libc::EHOSTUNREACH => "EHOSTUNREACH",
+                libc::EHOSTDOWN => "EHOSTDOWN",
+                libc::ENETUNREACH => "ENETUNREACH",
+                libc::ENETDOWN => "ENETDOWN",
+                libc::ENOTCONN => "ENOTCONN",
+                libc::EISCONN => "EISCONN",
+                libc::ESHUTDOWN => "ESHUTDOWN",
+                libc::EAGAIN => "EAGAIN",
+                libc::EINPROGRESS => "EINPROGRESS",
+                libc::EALREADY => "EALREADY",
+                libc::EINTR => "EINTR",
+                libc::EADDRINUSE => "EADDRINUSE",
+                libc::EADDRNOTAVAIL => "EADDRNOTAVAIL",
+                libc::EPROTO => "EPROTO",
+                libc::EMSGSIZE => "EMSGSIZE",
+                libc::ENOBUFS => "ENOBUFS",
+                libc::ENOMEM => "ENOMEM",
+                libc::EMFILE => "EMFILE",
+                libc::ENFILE => "ENFILE",
+                libc::EBADF => "EBADF",
+                libc::EINVAL => "EINVAL",
+                libc::EACCES => "EACCES",
+                libc::EPERM => "EPERM",
+                _ => return None,
+            };
+            Some(name)
+        }
+        #[cfg(not(unix))]
+        None
+    }
+}
+
+/// `ConnectionReset (ECONNRESET)`, or just the kind if there was no OS error
+impl std::fmt::Debug for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.errno, self.errno_name()) {
+            (_, Some(name)) => write!(f, "{:?} ({})", self.kind, name),
+            (Some(errno), None) => write!(f, "{:?} (errno {})", self.kind, errno),
+            (None, None) => write!(f, "{:?}", self.kind),
+        }
+    }
+}
+
+impl SingleRunResult {
+    /// the read and write errors the run observed
+    fn errors(&self) -> (Option<RunError>, Option<RunError>) {
+        match self {
+            SingleRunResult::ResponseCorrect
+            | SingleRunResult::ResponseMismatch
+            | SingleRunResult::ReaderStuck
+            | SingleRunResult::ConnectError(_)
+            | SingleRunResult::ConnectTimeout => (None, None),
+            SingleRunResult::ReadResponseError(read) => (Some(*read), None),
+            SingleRunResult::ReadTimeout => (Some(RunError::timed_out()), None),
+            SingleRunResult::WriteTimeout => (None, Some(RunError::timed_out())),
+            SingleRunResult::WriteNumberError(write)
+            | SingleRunResult::PostResponseWriteError(write) => (None, Some(*write)),
+            SingleRunResult::BothErr { read, write, .. } => (Some(*read), Some(*write)),
+        }
+    }
+}
+
+/// What a single run observed besides its result category.
+pub struct RunRecord {
+    pub result: SingleRunResult,
+    pub connect: std::time::Duration,
+    local_addr: Option<net::SocketAddr>,
+    peer_addr: Option<net::SocketAddr>,
+    /// nanoseconds from the odd number's write returning to reading the response
+    odd_write_to_response: Option<i64>,
+    /// from the start of the exchange to reading the response
+    pub response_after: Option<std::time::Duration>,
+    /// from the start of the exchange to the failed write
+    pub write_error_after: Option<std::time::Duration>,
+    /// our teardown and close of the connection, if we tore it down after the
+    /// exchange rather than within it
+    pub close: Option<std::time::Duration>,
+    /// request stream bytes handed to the connection's writer, before compression
+    bytes_written: u64,
+    /// with `--passive-close`, whether the server's FIN arrived before we closed
+    peer_fin: Option<bool>,
+    /// with `--tls`, how the server ended the connection after the response
+    tls_eof: Option<TlsEof>,
+    /// with `--classify-teardown`, how the server ended the connection after
+    /// the response and how long after it
+    peer_teardown: Option<(PeerTeardown, std::time::Duration)>,
+    /// with `--report-socket-states`, the kernel state of our and the server's
+    /// socket right after our close
+    socket_states: Option<(&'static str, &'static str)>,
+    /// for failed runs, the phase the failure was first noticed in
+    phase: Option<Phase>,
+    /// with `--verify-sequence`, numbers handed to the connection's writer
+    sent: Option<u32>,
+    /// with `--verify-sequence`, what the server's response proved
+    proof: Option<SequenceProof>,
+}
+
+/// What a `--verify-sequence` response proves the server received.
+#[derive(Clone, Copy)]
+struct SequenceProof {
+    /// numbers read in sequence from the start of the stream, the odd one included
+    in_sequence: u32,
+    /// the server's teardown mode for the connection
+    mode: TeardownMode,
+}
+
+impl SequenceProof {
+    /// decode the response bytes following the echoed odd number
+    fn decode(endian: Endian, buf: &[u8]) -> Option<SequenceProof> {
+        let in_sequence = endian.read_u32(&buf[..4]);
+        let code = endian.read_u32(&buf[4..8]);
+        match TeardownMode::from_code(code) {
+            Some(mode) => Some(SequenceProof { in_sequence, mode }),
+            None => {
+                log::warn!("server sent unknown teardown mode #{}", code);
+                None
+            }
+        }
+    }
+}
+
+/// What either side of a client connection observed.
+struct Exchange {
+    read_res: io::Result<u32>,
+    /// when the response (or the read error) was read
+    read_at: std::time::Instant,
+    /// when and how writing the request stream failed
+    write_err: Option<(std::time::Instant, io::Error)>,
+    /// whether the failed write carried the odd number
+    odd_write_failed: bool,
+    /// when the write handing the odd number to the kernel returned
+    odd_written_at: Option<std::time::Instant>,
+    /// whether the response reader had to be abandoned
+    reader_stuck: bool,
+    /// numbers handed to the connection's writer
+    sent: u32,
+    /// with `--verify-sequence`, the rest of the response
+    proof: Option<SequenceProof>,
+}
+
+/// How far `Client::write_request_stream` got.
+struct StreamWritten {
+    /// when and how writing failed
+    write_err: Option<(std::time::Instant, io::Error)>,
+    /// whether the failed write carried the odd number
+    odd_write_failed: bool,
+    /// when the write handing the odd number to the kernel returned
+    odd_written_at: Option<std::time::Instant>,
+    /// numbers handed to the writer
+    sent: u32,
+}
+
+/// Which side of a connection failed first when both did.
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd)]
+pub enum ErrorOrder {
+    ReadFirst,
+    WriteFirst,
+}
+
+/// `Write` into a buffer shared with the single-threaded send loop.
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How long the response reader may take beyond `--read-timeout` before it
+/// counts as stuck.
+const READER_GRACE: std::time::Duration = std::time::Duration::from_secs(1);
+
+impl Client {
+    pub fn run(&self) -> Result<(), failure::Error> {
+        if self.responses == 0 {
+            failure::bail!("--responses must be at least 1");
+        }
+        if self.single_thread && self.responses != 1 {
+            failure::bail!("--single-thread reads exactly one response");
+        }
+        if self.batch_size == 0 {
+            failure::bail!("--batch-size must be at least 1");
+        }
+        if self.single_thread && self.send_after_response {
+            failure::bail!("--single-thread stops sending once the response arrived");
+        }
+        if self.single_thread && self.batch_size != 1 {
+            failure::bail!("--single-thread writes whatever the socket accepts, not batches");
+        }
+        if self.preconnect == Some(0) {
+            failure::bail!("--preconnect must be at least 1");
+        }
+        if self.write_timeout.is_some()
+            && (self.io_uring
+                || self.nonblocking
+                || self.single_thread
+                || matches!(self.backend, Backend::Tokio))
+        {
+            failure::bail!(
+                "--write-timeout applies to blocking writes, not --io-uring, --nonblocking, --single-thread or --backend tokio"
+            );
+        }
+        if let Transport::Unix = self.transport {
+            if self.connect_timeout.is_some() {
+                failure::bail!("--connect-timeout applies to TCP handshakes, not --transport unix");
+            }
+            if self.keepalive.keepalive
+                || self.tcp_user_timeout.is_some()
+                || self.nodelay
+                || self.sndbuf.is_some()
+                || self.rcvbuf.is_some()
+                || self.linger.is_some()
+                || !self.sockopts.is_empty()
+            {
+                failure::bail!(
+                    "--keepalive, --tcp-user-timeout, --nodelay, --sndbuf, --rcvbuf, --linger and --sockopt apply to TCP, not --transport unix"
+                );
+            }
+        }
+        if self.concurrency == 0 {
+            failure::bail!("--concurrency must be at least 1");
+        }
+        if self.concurrency > 1 && (self.preconnect.is_some() || self.repeat_forever || self.fuzz) {
+            failure::bail!(
+                "--concurrency runs a batch of --times requests, without --preconnect, --repeat-forever or --fuzz"
+            );
+        }
+        if let Some(odd_after) = self.odd_after {
+            if self.payload_file.is_some() {
+                failure::bail!("--payload-file decides where the odd number is, not --odd-after");
+            }
+            if let (OddAfter::Bytes(_), Framing::LengthPrefixed) = (odd_after, self.framing) {
+                failure::bail!(
+                    "--odd-after in bytes needs --framing numbers, give a message count instead"
+                );
+            }
+            if self.odd_index() >= self.send_count {
+                failure::bail!(
+                    "--odd-after {} is beyond the {} messages of --send-count, no odd number would be sent",
+                    odd_after,
+                    self.send_count
+                );
+            }
+        }
+        if self.repeat_forever && matches!(self.output, Output::Json) {
+            // the document holds every run, which would grow without bound
+            failure::bail!("--output json lists every run, use --output csv with --repeat-forever");
+        }
+        if self.preconnect.is_some() && (self.retry_request > 0 || self.repeat_forever) {
+            failure::bail!("--preconnect runs a fixed number of connections, without --retry-request or --repeat-forever");
+        }
+        if self.verify_sequence {
+            if self.payload_file.is_some() {
+                failure::bail!(
+                    "--verify-sequence generates its own number stream, not --payload-file"
+                );
+            }
+            if let Framing::LengthPrefixed = self.framing {
+                failure::bail!(
+                    "--verify-sequence needs --framing numbers, length prefixes are truncated"
+                );
+            }
+            if self.single_thread {
+                failure::bail!("--verify-sequence reads the longer response on the reader thread, not with --single-thread");
+            }
+            if self.send_count > u32::MAX / 2 {
+                failure::bail!(
+                    "--verify-sequence numbers are twice the index, --send-count is too large"
+                );
+            }
+        }
+        match self.teardown_mode {
+            TeardownMode::CloseAfterNEvenNumbers | TeardownMode::FloodThenClose => {
+                failure::bail!("--teardown-mode {} is server only", self.teardown_mode)
+            }
+            TeardownMode::CloseImmediately => {}
+            _ if self.passive_close => failure::bail!(
+                "--passive-close closes once the server's FIN arrived, use --teardown-mode drain-then-close instead"
+            ),
+            _ => {}
+        }
+        if self.tls && !cfg!(feature = "tls") {
+            failure::bail!("--tls requires building with the tls cargo feature");
+        }
+        if let Backend::Tokio = self.backend {
+            if !cfg!(feature = "tokio") {
+                failure::bail!("--backend tokio requires building with the tokio cargo feature");
+            }
+            if self.single_thread || self.responses != 1 {
+                failure::bail!("--backend tokio reads exactly one response in its own task, without --single-thread");
+            }
+            if let Compress::Gzip = self.compress {
+                failure::bail!("--backend tokio does not support --compress");
+            }
+        }
+        if self.tls
+            && (matches!(self.transport, Transport::Unix)
+                || matches!(self.backend, Backend::Tokio)
+                || self.io_uring
+                || self.single_thread
+                || self.nonblocking
+                || self.responses != 1
+                || self.fuzz)
+        {
+            failure::bail!("--tls reads one response on a blocking TCP socket with --backend std, without --io-uring or --fuzz");
+        }
+        if let Transport::Unix = self.transport {
+            if matches!(self.backend, Backend::Tokio)
+                || self.io_uring
+                || self.single_thread
+                || self.nonblocking
+                || self.responses != 1
+                || self.preconnect.is_some()
+                || self.fuzz
+                || self.bind.is_some()
+                || self.syn_retries.is_some()
+                || self.max_retries.is_some()
+                || self.dump_sockopts
+                || self.tcp_info
+            {
+                failure::bail!("--transport unix reads one response on a blocking socket, without TCP options, --preconnect or --fuzz");
+            }
+            match self.teardown_mode {
+                TeardownMode::ResetImmediately
+                | TeardownMode::ShutdownWriteWaitFinThenReset
+                | TeardownMode::LingerZeroThenShutdown => failure::bail!(
+                    "--teardown-mode {} has no equivalent on Unix domain sockets",
+                    self.teardown_mode
+                ),
+                _ => {}
+            }
+        }
+        if self.io_uring {
+            if !cfg!(feature = "io-uring") {
+                failure::bail!("--io-uring requires building with the io-uring cargo feature");
+            }
+            if self.single_thread || matches!(self.backend, Backend::Tokio) {
+                failure::bail!("--io-uring only applies to the threaded std backend");
+            }
+        }
+        if let Some(path) = &self.pcap {
+            if let Transport::Unix = self.transport {
+                failure::bail!("--pcap captures TCP, not --transport unix");
+            }
+            let port = self.server_addr().context("resolve server address")?.port();
+            capture::start(path, port)?;
+        }
+        if self.fuzz {
+            let outcomes = self.run_fuzz();
+            println!("fuzz outcomes:\n{:#?}", outcomes);
+            return Ok(());
+        }
+        let payload = self.load_payload()?;
+        if let Output::Csv = self.output {
+            self.write_csv_header().context("write CSV header")?;
+        }
+        // create the summary file up front so we don't find out after a long run
+        let summary_out = match &self.summary_out {
+            Some(path) => Some((
+                path,
+                std::fs::File::create(path)
+                    .with_context(|_| format!("create summary file {:?}", path))?,
+            )),
+            None => None,
+        };
+        let run_log = RunLog::open(self.csv.as_deref())?;
+        if let Some(start_at) = self.start_at {
+            match start_at.duration_since(std::time::SystemTime::now()) {
+                Ok(wait) => {
+                    log::info!("waiting {:?} until --start-at", wait);
+                    spin_sleep::sleep(wait);
+                }
+                Err(e) => log::warn!("--start-at is {:?} in the past, starting now", e.duration()),
+            }
+            log::info!(
+                "starting at {}",
+                humantime::format_rfc3339_nanos(std::time::SystemTime::now())
+            );
+        }
+        let mut stats = if self.repeat_forever {
+            self.run_forever(payload.as_deref(), &run_log)?
+        } else {
+            self.run_batch(payload.as_deref(), &run_log)
+        };
+        if self.report_socket_states {
+            stats.time_wait_after = self.count_time_wait();
+        }
+        if let Some(prefix) = &self.hgrm_out {
+            stats.latencies.write_hgrm(prefix)?;
+        }
+        match summary_out {
+            Some((path, mut file)) => self
+                .write_stats(&mut file, "multi run stats", &stats)
+                .with_context(|_| format!("write summary file {:?}", path))?,
+            None => self.print_stats("multi run stats", &stats),
+        }
+        Ok(())
+    }
+
+    fn print_stats(&self, title: &str, stats: &ClientStats) {
+        self.write_stats(&mut io::stdout().lock(), title, stats)
+            .expect("write stats to stdout");
+    }
+
+    fn write_stats<W: Write>(
+        &self,
+        out: &mut W,
+        title: &str,
+        stats: &ClientStats,
+    ) -> io::Result<()> {
+        let mut text = format!("{}:\n{:#?}\n", title, stats.runs);
+        if self.retry_request > 0 {
+            text += &format!(
+                "logical requests succeeded by connections used:\n{:#?}\nlogical requests failed: {}\n",
+                stats.requests_succeeded, stats.requests_failed
+            );
+        }
+        let delta = &stats.odd_write_to_response;
+        if let (Some(min), Some(mean), Some(max), Some(p)) = (
+            delta.min,
+            delta.mean(),
+            delta.max,
+            delta.percentiles(&[50.0, 90.0, 99.0]),
+        ) {
+            text += &format!(
+                "request RTT (odd number write to response): min {}ns mean {}ns p50 {}ns p90 {}ns p99 {}ns max {}ns over {} runs\n",
+                min, mean, p[0], p[1], p[2], max, delta.count
+            );
+        }
+        text += &stats.latencies.summary();
+        if self.count_by_phase {
+            text += &format!("failures by phase:\n{:#?}\n", stats.phases);
+        }
+        if self.verify_sequence {
+            text += &format!(
+                "data loss by server teardown mode:\n{:#?}\n",
+                stats.data_loss
+            );
+        }
+        if self.passive_close {
+            text += &format!(
+                "passive close: server FIN observed on {} runs, missed on {} runs\n",
+                stats.peer_fin_observed, stats.peer_fin_missed
+            );
+        }
+        if self.classify_teardown {
+            text += "how the server ended connections after the response:\n";
+            for (teardown, after) in &stats.peer_teardowns {
+                if let (Some(max), Some(p)) = (after.max, after.percentiles(&[50.0, 99.0])) {
+                    text += &format!(
+                        "  {:?}: {} runs, after p50 {}ns p99 {}ns max {}ns\n",
+                        teardown, after.count, p[0], p[1], max
+                    );
+                }
+            }
+        }
+        if self.tls {
+            text += &format!(
+                "how the server ended TLS connections after the response:\n{:#?}\n",
+                stats.tls_eofs
+            );
+        }
+        if self.report_socket_states {
+            text += &format!(
+                "socket states right after the client's close (client / server):\n{:#?}\n",
+                stats.socket_states
+            );
+            if let Some((ours, servers)) = stats.time_wait_after {
+                text += &format!(
+                    "TIME_WAIT sockets after the runs: {} on the client side, {} on the server side\n",
+                    ours, servers
+                );
+            }
+        }
+        if self.error_timeline {
+            text += &self.error_timeline_text(&stats.errors);
+        }
+        match self.output {
+            Output::Pretty => out.write_all(text.as_bytes()),
+            Output::Csv => text
+                .lines()
+                .try_for_each(|line| writeln!(out, "# {}", line)),
+            Output::Json => {
+                serde_json::to_writer(&mut *out, &self.stats_json(title, stats))?;
+                writeln!(out)
+            }
+        }
+    }
+
+    /// the failed runs with the gap to the previous failure, in the `--output` format
+    fn error_timeline_text(&self, errors: &[ErrorEvent]) -> String {
+        let gaps = errors.iter().scan(None, |prev, e| {
+            let gap = prev.and_then(|prev| e.at.duration_since(prev).ok());
+            *prev = Some(e.at);
+            Some((e, gap))
+        });
+        match self.output {
+            Output::Pretty | Output::Json => {
+                let mut text = format!("error timeline ({} errors):\n", errors.len());
+                for (e, gap) in gaps {
+                    text += &format!(
+                        "  run {} at {}, gap {}: {}\n",
+                        e.run,
+                        humantime::format_rfc3339_nanos(e.at),
+                        gap.map(|gap| format!("{:?}", gap))
+                            .unwrap_or_else(|| "-".to_string()),
+                        e.result
+                    );
+                }
+                text
+            }
+            Output::Csv => {
+                let mut w = csv::Writer::from_writer(Vec::new());
+                w.write_record(["error_run", "at", "gap_ns", "result"])
+                    .expect("encoding to memory cannot fail");
+                for (e, gap) in gaps {
+                    w.write_record([
+                        e.run.to_string(),
+                        humantime::format_rfc3339_nanos(e.at).to_string(),
+                        gap.map(|gap| gap.as_nanos().to_string())
+                            .unwrap_or_default(),
+                        e.result.clone(),
+                    ])
+                    .expect("encoding to memory cannot fail");
+                }
+                String::from_utf8(w.into_inner().expect("encoding to memory cannot fail"))
+                    .expect("CSV of UTF-8 fields is UTF-8")
+            }
+        }
+    }
+
+    /// columns of `csv_row`
+    const CSV_COLUMNS: [&'static str; 13] = [
+        "run",
+        "result",
+        "read_error",
+        "write_error",
+        "connect_ns",
+        "local_port",
+        "peer_addr",
+        "rtt_ns",
+        "read_errno",
+        "write_errno",
+        "bytes_written",
+        "peer_teardown",
+        "peer_teardown_ns",
+    ];
+
+    fn write_csv_header(&self) -> Result<(), failure::Error> {
+        let mut w = csv::Writer::from_writer(io::stdout());
+        w.write_record(Self::CSV_COLUMNS)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_row(&self, run: usize, record: &RunRecord) -> Result<(), failure::Error> {
+        let mut w = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(io::stdout());
+        w.write_record(Self::csv_row(run, record))?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// a run as a row of `--output csv` and the `--csv` file
+    fn csv_row(run: usize, record: &RunRecord) -> [String; 13] {
+        let (read_err, write_err) = record.result.errors();
+        let kind = |e: Option<RunError>| e.map(|e| format!("{:?}", e.kind)).unwrap_or_default();
+        let errno = |e: Option<RunError>| {
+            e.and_then(|e| {
+                e.errno_name()
+                    .map(str::to_string)
+                    .or(e.errno.map(|n| n.to_string()))
+            })
+            .unwrap_or_default()
+        };
+        [
+            run.to_string(),
+            record.result.to_string(),
+            kind(read_err),
+            kind(write_err),
+            record.connect.as_nanos().to_string(),
+            record
+                .local_addr
+                .map(|a| a.port().to_string())
+                .unwrap_or_default(),
+            record.peer_addr.map(|a| a.to_string()).unwrap_or_default(),
+            record
+                .odd_write_to_response
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            errno(read_err),
+            errno(write_err),
+            record.bytes_written.to_string(),
+            record
+                .peer_teardown
+                .map(|(teardown, _)| format!("{:?}", teardown))
+                .unwrap_or_default(),
+            record
+                .peer_teardown
+                .map(|(_, after)| after.as_nanos().to_string())
+                .unwrap_or_default(),
+        ]
+    }
+
+    /// a run as an element of the `--output json` document's `run_details`
+    fn run_json(&self, run: usize, record: &RunRecord) -> serde_json::Value {
+        let (read_err, write_err) = record.result.errors();
+        let kind = |e: Option<RunError>| e.map(|e| format!("{:?}", e.kind));
+        serde_json::json!({
+            "run": run,
+            "result": record.result.to_string(),
+            "read_error": kind(read_err),
+            "read_errno": read_err.and_then(|e| e.errno),
+            "read_errno_name": read_err.and_then(|e| e.errno_name()),
+            "write_error": kind(write_err),
+            "write_errno": write_err.and_then(|e| e.errno),
+            "write_errno_name": write_err.and_then(|e| e.errno_name()),
+            "connect_ns": record.connect.as_nanos() as u64,
+            "local_port": record.local_addr.map(|a| a.port()),
+            "peer_addr": record.peer_addr.map(|a| a.to_string()),
+            "rtt_ns": record.odd_write_to_response,
+            "phase": record.phase.map(|phase| format!("{:?}", phase)),
+            "peer_fin": record.peer_fin,
+            "tls_eof": record.tls_eof.map(|eof| format!("{:?}", eof)),
+            "peer_teardown": record.peer_teardown.map(|(teardown, after)| serde_json::json!({
+                "kind": format!("{:?}", teardown),
+                "after_ns": after.as_nanos() as u64,
+            })),
+            "socket_states": record.socket_states.map(|(ours, servers)| serde_json::json!({
+                "client": ours,
+                "server": servers,
+            })),
+        })
+    }
+
+    /// the stats as an `--output json` document
+    fn stats_json(&self, title: &str, stats: &ClientStats) -> serde_json::Value {
+        let debug_keys = |counts: Vec<(String, usize)>| {
+            counts
+                .into_iter()
+                .map(|(key, count)| (key, serde_json::json!(count)))
+                .collect::<serde_json::Map<_, _>>()
+        };
+        let delta = &stats.odd_write_to_response;
+        let rtt = delta.percentiles(&[50.0, 90.0, 99.0]).map(|p| {
+            serde_json::json!({
+                "min_ns": delta.min,
+                "mean_ns": delta.mean().map(|mean| mean as i64),
+                "p50_ns": p[0],
+                "p90_ns": p[1],
+                "p99_ns": p[2],
+                "max_ns": delta.max,
+                "count": delta.count,
+            })
+        });
+        serde_json::json!({
+            "schema": 1,
+            "title": title,
+            "params": {
+                "args": std::env::args().collect::<Vec<_>>(),
+                "server": self.server,
+                "times": self.times,
+                "send_count": self.send_count,
+                "odd_first": self.odd_first,
+                "odd_after": self.odd_after.map(|o| o.to_string()),
+                "framing": self.framing.to_string(),
+                "word_size": self.word_size.to_string(),
+                "keepalive": self.keepalive.json(),
+                "tcp_user_timeout": self.tcp_user_timeout.map(|d| d.to_string()),
+                "nodelay": self.nodelay,
+                "sndbuf": self.sndbuf,
+                "rcvbuf": self.rcvbuf,
+                "linger": self.linger.map(|d| d.to_string()),
+                "sockopts": self.sockopts.iter().map(|opt| opt.to_string()).collect::<Vec<_>>(),
+                "compress": self.compress.to_string(),
+                "endian": self.endian.to_string(),
+                "batch_size": self.batch_size,
+                "responses": self.responses,
+                "single_thread": self.single_thread,
+                "nonblocking": self.nonblocking,
+                "send_after_response": self.send_after_response,
+                "teardown_mode": self.teardown_mode.to_string(),
+                "verify_sequence": self.verify_sequence,
+            },
+            "runs": debug_keys(
+                stats.runs.iter().map(|(res, &n)| (format!("{:?}", res), n)).collect()
+            ),
+            "requests_succeeded_by_connections": debug_keys(
+                stats.requests_succeeded.iter().map(|(&conns, &n)| (conns.to_string(), n)).collect()
+            ),
+            "requests_failed": stats.requests_failed,
+            "request_rtt": rtt,
+            "latencies": stats.latencies.json(),
+            "failures_by_phase": debug_keys(
+                stats.phases.iter().map(|(phase, &n)| (format!("{:?}", phase), n)).collect()
+            ),
+            "data_loss": stats.data_loss.iter().map(|(mode, loss)| {
+                (mode.clone(), serde_json::json!({
+                    "runs": loss.runs,
+                    "sent": loss.sent,
+                    "proven": loss.proven,
+                    "unproven": loss.unproven,
+                }))
+            }).collect::<serde_json::Map<_, _>>(),
+            "peer_fin_observed": stats.peer_fin_observed,
+            "peer_fin_missed": stats.peer_fin_missed,
+            "peer_teardowns": stats.peer_teardowns.iter().map(|(teardown, after)| {
+                let p = after.percentiles(&[50.0, 99.0]);
+                (format!("{:?}", teardown), serde_json::json!({
+                    "count": after.count,
+                    "p50_ns": p.as_ref().map(|p| p[0]),
+                    "p99_ns": p.as_ref().map(|p| p[1]),
+                    "max_ns": after.max,
+                }))
+            }).collect::<serde_json::Map<_, _>>(),
+            "socket_states": stats.socket_states,
+            "time_wait_after": stats.time_wait_after.map(|(ours, servers)| serde_json::json!({
+                "client": ours,
+                "server": servers,
+            })),
+            "tls_eofs": debug_keys(
+                stats.tls_eofs.iter().map(|(eof, &n)| (format!("{:?}", eof), n)).collect()
+            ),
+            "errors": stats.errors.iter().map(|e| serde_json::json!({
+                "run": e.run,
+                "at": humantime::format_rfc3339_nanos(e.at).to_string(),
+                "result": e.result,
+            })).collect::<Vec<_>>(),
+            "run_details": stats.run_details,
+        })
+    }
+
+    /// perform requests until interrupted by Ctrl-C, printing the stats every `stats_interval`
+    fn run_forever(
+        &self,
+        payload: Option<&[u32]>,
+        run_log: &RunLog,
+    ) -> Result<ClientStats, failure::Error> {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        {
+            let interrupted = interrupted.clone();
+            ctrlc::set_handler(move || interrupted.store(true, atomic::Ordering::SeqCst))
+                .context("install Ctrl-C handler")?;
+        }
+
+        let mut stats = ClientStats::default();
+        let mut requests: u64 = 0;
+        let mut last_print = std::time::Instant::now();
+        while !interrupted.load(atomic::Ordering::SeqCst) {
+            self.request(payload, run_log, &mut stats);
+            requests += 1;
+
+            if last_print.elapsed() >= *self.stats_interval {
+                self.print_stats(&format!("stats after {} requests", requests), &stats);
+                last_print = std::time::Instant::now();
+            }
+        }
+        log::info!("interrupted after {} requests", requests);
+        Ok(stats)
+    }
+
+    /// perform `times` requests and count the results per category
+    pub fn run_batch(&self, payload: Option<&[u32]>, run_log: &RunLog) -> ClientStats {
+        let mut stats = ClientStats::default();
+        if let Some(preconnect) = self.preconnect {
+            let mut remaining = self.times;
+            while remaining > 0 {
+                let n = std::cmp::min(preconnect, remaining);
+                remaining -= n;
+                let conns: Vec<_> = (0..n).map(|_| self.connect()).collect();
+                log::info!("opened {} connections, sending on each", n);
+                for conn in conns {
+                    let record = match conn {
+                        Ok((conn, connect)) => self.run_conn(conn, connect, payload),
+                        Err((e, connect)) => Self::connect_failed(e, connect),
+                    };
+                    self.record_run(record, run_log, &mut stats);
+                }
+            }
+            return stats;
+        }
+        if self.concurrency > 1 {
+            return self.run_concurrent(payload, run_log);
+        }
+        for _ in 0..self.times {
+            self.request(payload, run_log, &mut stats);
+        }
+        stats
+    }
+
+    /// `run_batch` with the requests spread over `--concurrency` threads
+    fn run_concurrent(&self, payload: Option<&[u32]>, run_log: &RunLog) -> ClientStats {
+        let threads = self.concurrency.min(self.times).max(1);
+        let mut stats = ClientStats::default();
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|i| {
+                    let times = self.times / threads + usize::from(i < self.times % threads);
+                    scope.spawn(move || {
+                        let mut stats = ClientStats::default();
+                        for _ in 0..times {
+                            self.request(payload, run_log, &mut stats);
+                        }
+                        stats
+                    })
+                })
+                .collect();
+            for handle in handles {
+                stats.merge(handle.join().expect("client thread panicked"));
+            }
+        });
+        // the timeline's gaps are between failures of any thread
+        stats.errors.sort_by_key(|e| e.at);
+        stats
+    }
+
+    /// send `times` malformed request streams and count how the server reacted
+    fn run_fuzz(&self) -> std::collections::BTreeMap<FuzzOutcome, usize> {
+        let mut rng = match self.seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_rng(&mut rand::rng()),
+        };
+        let mut outcomes = std::collections::BTreeMap::new();
+        for _ in 0..self.times {
+            let input = Self::fuzz_input(&mut rng, self.endian, self.word_size);
+            let outcome = match self.connect() {
+                Ok((conn, _)) => self.fuzz_run(&conn, &input),
+                Err((e, _)) => {
+                    log::warn!("cannot connect: {:?}", e);
+                    FuzzOutcome::OtherError(e.kind())
+                }
+            };
+            log::info!("fuzz outcome: {:?}", outcome);
+            *outcomes.entry(outcome).or_insert(0) += 1;
+        }
+        outcomes
+    }
+
+    /// random bytes, or even numbers followed by a truncated number
+    fn fuzz_input<R: rand::Rng>(rng: &mut R, endian: Endian, word_size: WordSize) -> Vec<u8> {
+        let mut input = Vec::new();
+        if rng.random() {
+            let len = rng.random_range(0..64);
+            input.extend((0..len).map(|_| rng.random::<u8>()));
+            log::info!("fuzz input: {} random bytes", len);
+        } else {
+            let evens = rng.random_range(0..16);
+            for _ in 0..evens {
+                input.extend(word_size.encode(endian, rng.random::<u32>() & !1));
+            }
+            let partial = rng.random_range(1..word_size.bytes());
+            input.extend((0..partial).map(|_| rng.random::<u8>()));
+            log::info!(
+                "fuzz input: {} even numbers, then {} bytes of a number",
+                evens,
+                partial
+            );
+        }
+        input
+    }
+
+    /// write `input`, shut down our write side, and watch what the server does
+    fn fuzz_run(&self, conn: &TcpStream, input: &[u8]) -> FuzzOutcome {
+        let _span = ConnLogSpan::enter(conn.local_addr(), conn.peer_addr());
+        let res = (&*conn)
+            .write_all(input)
+            .and_then(|()| conn.shutdown(net::Shutdown::Write))
+            .and_then(|()| conn.set_read_timeout(Some(self.read_timeout.into())));
+        if let Err(e) = res {
+            return match e.kind() {
+                io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => FuzzOutcome::Reset,
+                kind => FuzzOutcome::OtherError(kind),
+            };
+        }
+        let mut response = Vec::new();
+        match (&*conn).read_to_end(&mut response) {
+            Ok(_) if response.is_empty() => FuzzOutcome::CleanClose,
+            Ok(_) => {
+                log::info!("response bytes before close: {:?}", response);
+                FuzzOutcome::ResponseThenClose
+            }
+            Err(e) => match e.kind() {
+                io::ErrorKind::ConnectionReset => FuzzOutcome::Reset,
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => FuzzOutcome::Hang,
+                kind => FuzzOutcome::OtherError(kind),
+            },
+        }
+    }
+
+    /// perform one logical request, reconnecting up to `retry_request` times
+    /// if a run does not end with a correct response
+    fn request(&self, payload: Option<&[u32]>, run_log: &RunLog, stats: &mut ClientStats) {
+        for connections in 1..=self.retry_request + 1 {
+            let record = self.single_run(payload);
+            if self.record_run(record, run_log, stats) {
+                *stats.requests_succeeded.entry(connections).or_insert(0) += 1;
+                return;
+            }
+            if connections <= self.retry_request {
+                log::info!("retrying request on connection #{}", connections + 1);
+            }
+        }
+        stats.requests_failed += 1;
+    }
+
+    /// count a run's outcome in `stats` and emit its CSV row, returning whether it succeeded
+    fn record_run(&self, record: RunRecord, run_log: &RunLog, stats: &mut ClientStats) -> bool {
+        log::info!("run result: {:?}", record.result);
+        let run = run_log.record(&record);
+        match self.output {
+            Output::Pretty => {}
+            Output::Csv => {
+                if let Err(e) = self.write_csv_row(run, &record) {
+                    log::error!("cannot write CSV row: {:?}", e);
+                }
+            }
+            // numbered per thread, `ClientStats::merge` offsets them
+            Output::Json => stats
+                .run_details
+                .push(self.run_json(stats.runs.values().sum(), &record)),
+        }
+        if let Some(delta) = record.odd_write_to_response {
+            stats.odd_write_to_response.record(delta);
+        }
+        stats.latencies.record(&record);
+        match record.peer_fin {
+            Some(true) => stats.peer_fin_observed += 1,
+            Some(false) => stats.peer_fin_missed += 1,
+            None => {}
+        }
+        if let Some(eof) = record.tls_eof {
+            *stats.tls_eofs.entry(eof).or_insert(0) += 1;
+        }
+        if let Some((teardown, after)) = record.peer_teardown {
+            stats
+                .peer_teardowns
+                .entry(teardown)
+                .or_default()
+                .record(after.as_nanos() as i64);
+        }
+        if let Some((ours, servers)) = record.socket_states {
+            *stats
+                .socket_states
+                .entry(format!("{} / {}", ours, servers))
+                .or_insert(0) += 1;
+        }
+        if let Some(phase) = record.phase {
+            *stats.phases.entry(phase).or_insert(0) += 1;
+        }
+        if let Some(sent) = record.sent {
+            let (key, proven) = match record.proof {
+                Some(proof) => (proof.mode.to_string(), proof.in_sequence),
+                None => ("no proof".to_string(), 0),
+            };
+            stats.data_loss.entry(key).or_default().record(sent, proven);
+        }
+        let succeeded = record.result == SingleRunResult::ResponseCorrect;
+        if self.error_timeline && !succeeded {
+            stats.errors.push(ErrorEvent {
+                run: stats.runs.values().sum(),
+                at: std::time::SystemTime::now(),
+                result: format!("{:?}", record.result),
+            });
+        }
+        *stats.runs.entry(record.result).or_insert(0) += 1;
+        succeeded
+    }
+
+    /// read `--payload-file` as big-endian numbers, zero-padding the last one
+    fn load_payload(&self) -> Result<Option<Vec<u32>>, failure::Error> {
+        let path = match &self.payload_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if let Framing::LengthPrefixed = self.framing {
+            failure::bail!("--payload-file requires numbers framing");
+        }
+        if let WordSize::U64 = self.word_size {
+            failure::bail!("--payload-file requires --word-size 4");
+        }
+        let mut bytes =
+            std::fs::read(path).with_context(|_| format!("read payload file {:?}", path))?;
+        let rem = bytes.len() % 4;
+        if rem != 0 {
+            bytes.resize(bytes.len() + 4 - rem, 0);
+            log::info!(
+                "zero-padded last number of payload file by {} bytes",
+                4 - rem
+            );
+        }
+        // decode with the wire byte order so the file's bytes are sent unchanged
+        Ok(Some(
+            bytes
+                .chunks(4)
+                .map(|chunk| self.endian.read_u32(chunk))
+                .collect(),
+        ))
+    }
+
+    /// enable SO_REUSEPORT, warning if the platform does not support it
+    fn set_reuse_port(socket: &socket2::Socket) {
+        #[cfg(unix)]
+        {
+            if let Err(e) = socket.set_reuse_port(true) {
+                log::warn!("cannot enable SO_REUSEPORT, continuing without: {:?}", e);
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = socket;
+            log::warn!("SO_REUSEPORT is not supported on this platform, continuing without");
+        }
+    }
+
+    /// apply `--syn-retries` and `--max-retries`, warning where they are not supported
+    fn set_retransmission_opts(&self, socket: &socket2::Socket) {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(syn_retries) = self.syn_retries {
+                let val = libc::c_int::from(syn_retries);
+                match setsockopt_int(socket, libc::IPPROTO_TCP, libc::TCP_SYNCNT, val) {
+                    Ok(()) => log::info!("set TCP_SYNCNT to {}", val),
+                    Err(e) => log::warn!("cannot set TCP_SYNCNT, continuing without: {:?}", e),
+                }
+            }
+            if let Some(max_retries) = self.max_retries {
+                // Linux has no per-socket TCP_RETRIES2, only the
+                // net.ipv4.tcp_retries2 sysctl.
+                log::warn!(
+                    "TCP_RETRIES2 cannot be set per socket, ignoring --max-retries {} (use sysctl net.ipv4.tcp_retries2)",
+                    max_retries
+                );
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = socket;
+            if self.syn_retries.is_some() || self.max_retries.is_some() {
+                log::warn!("--syn-retries and --max-retries are Linux only, continuing without");
+            }
+        }
+    }
+
+    /// the odd number the server should echo, if the request stream has one
+    fn expected_response(&self, payload: Option<&[u32]>) -> Option<u32> {
+        (0..self.stream_len(payload))
+            .map(|i| self.stream_number(i, payload))
+            .find(|num| num % 2 == 1)
+    }
+
+    /// bytes of the first `n` messages of the request stream, before compression
+    fn stream_bytes(&self, n: u32, payload: Option<&[u32]>) -> u64 {
+        let word = self.word_size.bytes() as u64;
+        match self.framing {
+            Framing::Numbers => u64::from(n) * word,
+            Framing::LengthPrefixed => (0..n)
+                .map(|i| word + u64::from(self.stream_number(i, payload)))
+                .sum(),
+        }
+    }
+
+    /// number of messages in the request stream
+    fn stream_len(&self, payload: Option<&[u32]>) -> u32 {
+        payload.map_or(self.send_count, |p| p.len() as u32)
+    }
+
+    /// index of the odd number in the generated request stream
+    fn odd_index(&self) -> u32 {
+        match self.odd_after {
+            _ if self.odd_first => 0,
+            None => self.send_count / 2,
+            Some(OddAfter::Messages(n)) => n,
+            Some(OddAfter::Bytes(n)) => {
+                let word = self.word_size.bytes() as u64;
+                n.div_ceil(word).min(u64::from(u32::MAX)) as u32
+            }
+        }
+    }
+
+    /// the `i`-th number of the request stream
+    fn stream_number(&self, i: u32, payload: Option<&[u32]>) -> u32 {
+        if let Some(payload) = payload {
+            // Send the file's contents as is, the server finds the odd number.
+            return payload[i as usize];
+        }
+
+        let odd_index = self.odd_index();
+        let mut num = if self.verify_sequence {
+            // every number is unique, the server can tell how far it read in sequence
+            2 * i + u32::from(i == odd_index)
+        } else if i == odd_index {
+            // We are in the middle of the number stream (or at its start).
+            // Up until now, we only sent even numbers.
+            // Now send a single odd number, then proceed with even numbers.
+            23
+        } else {
+            // Produce even numbers by rounding down.
+            i & !1
+        };
+        if let Framing::LengthPrefixed = self.framing {
+            // The number is the payload length, keep messages small.
+            num %= 256;
+        }
+        num
+    }
+
+    /// send the request stream from this thread while a separate thread reads the response
+    ///
+    /// Mirrors `exchange_tokio` over a Unix domain socket.
+    fn exchange_unix(&self, conn: &UnixStream, payload: Option<&[u32]>) -> Exchange {
+        let (response_tx, response_rx) = crossbeam_channel::bounded(1);
+        let mut reader_conn = conn.try_clone().expect("cannot clone connection handle");
+        reader_conn
+            .set_read_timeout(Some(self.read_timeout.into()))
+            .expect("cannot set read timeout");
+        let endian = self.endian;
+        let len = if self.verify_sequence { 12 } else { 4 };
+        std::thread::spawn(move || {
+            let mut buf = [0_u8; 12];
+            let res = reader_conn
+                .read_exact(&mut buf[..len])
+                .map(|()| endian.read_u32(&buf[..4]));
+            let proof = match res {
+                Ok(_) if len > 4 => SequenceProof::decode(endian, &buf[4..len]),
+                _ => None,
+            };
+            let _ = response_tx.send((std::time::Instant::now(), res, proof));
+        });
+
+        let mut wr = self.compress.writer(BufWriter::new(conn));
+        let written = self.write_request_stream(&mut wr, payload, || !response_rx.is_empty());
+        drop(wr);
+
+        let reader_wait = *self.read_timeout + READER_GRACE;
+        let (read_at, read_res, proof, reader_stuck) = match response_rx.recv_timeout(reader_wait) {
+            Ok((read_at, read_res, proof)) => {
+                log::info!("server response received {:?}", read_res);
+                (read_at, read_res, proof, false)
+            }
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                log::warn!(
+                    "response reader still blocked after {:?}, abandoning it",
+                    reader_wait
+                );
+                let _ = conn.shutdown(net::Shutdown::Both);
+                let e = io::Error::new(io::ErrorKind::TimedOut, "response reader stuck");
+                (std::time::Instant::now(), Err(e), None, true)
+            }
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                panic!("receiver thread panicked")
+            }
+        };
+        Exchange {
+            read_res,
+            read_at,
+            write_err: written.write_err,
+            odd_write_failed: written.odd_write_failed,
+            odd_written_at: written.odd_written_at,
+            reader_stuck,
+            sent: written.sent,
+            proof,
+        }
+    }
+
+    /// write the request stream over TLS, then read the response and how the server ended the connection
+    ///
+    /// All on this thread, a rustls session can't be shared with a reader thread.
+    #[cfg(feature = "tls")]
+    fn exchange_tls(&self, conn: TcpStream, payload: Option<&[u32]>) -> (Exchange, Option<TlsEof>) {
+        conn.set_read_timeout(Some(self.read_timeout.into()))
+            .expect("cannot set read timeout");
+        let session = rustls::ClientConnection::new(tls::client_config(), tls::server_name())
+            .expect("cannot set up TLS session");
+        let mut stream = rustls::StreamOwned::new(session, conn);
+
+        let mut wr = self.compress.writer(BufWriter::new(&mut stream));
+        let written = self.write_request_stream(&mut wr, payload, || false);
+        drop(wr);
+
+        let mut buf = [0_u8; 12];
+        let len = if self.verify_sequence { 12 } else { 4 };
+        let read_res = stream
+            .read_exact(&mut buf[..len])
+            .map(|()| self.endian.read_u32(&buf[..4]));
+        let read_at = std::time::Instant::now();
+        log::info!("server response received {:?}", read_res);
+        let proof = match read_res {
+            Ok(_) if len > 4 => SequenceProof::decode(self.endian, &buf[4..len]),
+            _ => None,
+        };
+        let tls_eof = read_res
+            .as_ref()
+            .ok()
+            .map(|_| Self::await_tls_eof(&mut stream));
+        let exchange = Exchange {
+            read_res,
+            read_at,
+            write_err: written.write_err,
+            odd_write_failed: written.odd_write_failed,
+            odd_written_at: written.odd_written_at,
+            reader_stuck: false,
+            sent: written.sent,
+            proof,
+        };
+        (exchange, tls_eof)
+    }
+
+    /// read until the server ends the TLS session, and categorize how
+    #[cfg(feature = "tls")]
+    fn await_tls_eof<S: Read + Write>(
+        stream: &mut rustls::StreamOwned<rustls::ClientConnection, S>,
+    ) -> TlsEof {
+        let mut buf = vec![0_u8; 1 << 12];
+        let eof = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break TlsEof::CloseNotify,
+                Ok(n) => log::debug!("{} bytes after the response", n),
+                Err(e) => {
+                    break match e.kind() {
+                        io::ErrorKind::UnexpectedEof => TlsEof::Truncated,
+                        io::ErrorKind::ConnectionReset => TlsEof::Reset,
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => TlsEof::Hang,
+                        kind => TlsEof::OtherError(kind),
+                    }
+                }
+            }
+        };
+        log::info!("server ended the TLS connection: {:?}", eof);
+        eof
+    }
+
+    /// encode the numbers `range` of the stream into `batch`, returning whether
+    /// it holds the request, i.e. the first odd number unless already `request_flushed`
+    fn encode_batch(
+        &self,
+        batch: &mut Vec<u8>,
+        range: std::ops::Range<u32>,
+        payload: Option<&[u32]>,
+        request_flushed: bool,
+    ) -> bool {
+        batch.clear();
+        let mut batch_has_request = false;
+        for i in range {
+            let num = self.stream_number(i, payload);
+            self.framing
+                .write_message(&mut *batch, self.endian, self.word_size, num)
+                .expect("encoding to memory cannot fail");
+            batch_has_request |= !request_flushed && num % 2 == 1;
+        }
+        batch_has_request
+    }
+
+    /// write the request stream in batches, until it ends, a write fails, or
+    /// `response_arrived` unless `--send-after-response`
+    fn write_request_stream(
+        &self,
+        wr: &mut dyn Write,
+        payload: Option<&[u32]>,
+        mut response_arrived: impl FnMut() -> bool,
+    ) -> StreamWritten {
+        let mut write_err: Option<(std::time::Instant, io::Error)> = None;
+        let mut request_flushed = false;
+        let mut odd_written_at = None;
+        let mut odd_write_failed = false;
+        let mut sent = 0;
+        let stream_len = self.stream_len(payload);
+        let mut batch = Vec::new();
+        for batch_start in (0..stream_len).step_by(self.batch_size as usize) {
+            if response_arrived() && !self.send_after_response {
+                log::info!("server response received, stopping sender");
+                break;
+            }
+
+            // with the default batch size of 1 this is a single number
+            let batch_end = std::cmp::min(batch_start.saturating_add(self.batch_size), stream_len);
+            let batch_has_request =
+                self.encode_batch(&mut batch, batch_start..batch_end, payload, request_flushed);
+
+            let mut write_res = wr.write_all(&batch);
+            if (batch_has_request || self.batch_size > 1) && write_res.is_ok() {
+                // Don't let the request wait in the buffer for the following
+                // even numbers, the server only responds once it sees it.
+                // With --odd-first, this also makes it its own first write.
+                // Batches always go out as a whole.
+                write_res = wr.flush();
+            }
+            if batch_has_request && write_res.is_ok() {
+                request_flushed = true;
+                odd_written_at = Some(std::time::Instant::now());
+            }
+            if let Err(e) = write_res {
+                write_err = Some((std::time::Instant::now(), e));
+                odd_write_failed = batch_has_request;
+                break;
+            }
+            sent = batch_end;
+        }
+        // Push out whatever is still buffered, otherwise a short number stream
+        // may never reach the server and the reader waits forever.
+        if write_err.is_none() {
+            write_err = wr.flush().err().map(|e| (std::time::Instant::now(), e));
+        }
+        StreamWritten {
+            write_err,
+            odd_write_failed,
+            odd_written_at,
+            sent,
+        }
+    }
+
+    /// send the request stream from this thread while a separate thread reads the response
+    fn exchange_threaded(&self, conn: TcpStream, payload: Option<&[u32]>) -> Exchange {
+        // The response reader thread pushes every response it reads onto this
+        // channel, the first one tells the number-write loop to stop sending.
+        let (responses_tx, responses_rx) =
+            crossbeam_channel::bounded(self.response_channel_capacity);
+
+        // shuts the connection down to unblock a stuck response reader
+        let reader_unblocker = conn.try_clone().expect("cannot clone connection handle");
+
+        // Start a thread that reads the server's responses
+        let server_response_reader = {
+            let conn = conn.try_clone().expect("cannot clone connection handle");
+            let endian = self.endian;
+            let responses = self.responses;
+            let nonblocking = self.nonblocking;
+            let verify_sequence = self.verify_sequence;
+            // A nonblocking socket ignores the read timeout, only the grace period applies.
+            conn.set_read_timeout(Some(self.read_timeout.into()))
+                .expect("cannot set read timeout");
+            // returns how often the channel was full, i.e., the sender applied backpressure,
+            // and with --verify-sequence, the rest of the first response
+            std::thread::spawn(move || -> (u64, Option<SequenceProof>) {
+                let _span = ConnLogSpan::enter(conn.local_addr(), conn.peer_addr());
+                let mut channel_full = 0;
+                let mut proof = None;
+                for i in 0..responses {
+                    let mut buf = [0_u8; 12];
+                    let len = if verify_sequence && i == 0 { 12 } else { 4 };
+                    let res = if nonblocking {
+                        SpinOnWouldBlock(&conn).read_exact(&mut buf[..len])
+                    } else {
+                        // a read timeout surfaces as WouldBlock, don't spin on it
+                        (&conn).read_exact(&mut buf[..len]).map_err(|e| {
+                            if e.kind() == io::ErrorKind::WouldBlock {
+                                io::Error::new(io::ErrorKind::TimedOut, "read timed out")
+                            } else {
+                                e
+                            }
+                        })
+                    }
+                    .map(|_| endian.read_u32(&buf[..4]));
+                    if len > 4 && res.is_ok() {
+                        proof = SequenceProof::decode(endian, &buf[4..len]);
+                    }
+                    let failed = res.is_err();
+                    match responses_tx.try_send((std::time::Instant::now(), res)) {
+                        Ok(()) => {}
+                        Err(crossbeam_channel::TrySendError::Full(msg)) => {
+                            channel_full += 1;
+                            if responses_tx.send(msg).is_err() {
+                                break;
+                            }
+                        }
+                        Err(crossbeam_channel::TrySendError::Disconnected(_)) => break,
+                    }
+                    if failed {
+                        break;
+                    }
+                }
+                (channel_full, proof)
+            })
+        };
+
+        let mut buffered_conn = self
+            .compress
+            .writer(BufWriter::new(self.number_writer(&conn)));
+        // the response that arrived while sending, if any
+        let mut first_response = None;
+        let written = self.write_request_stream(&mut buffered_conn, payload, || {
+            if first_response.is_none() {
+                first_response = responses_rx.try_recv().ok();
+                if let Some((_, res)) = &first_response {
+                    log::info!("server response received {:?}", res);
+                }
+            }
+            first_response.is_some()
+        });
+
+        // Retrieve the response reader's results. Each read gives up after the
+        // read timeout, so waiting longer than that means the reader is stuck.
+        let reader_wait = *self.read_timeout + READER_GRACE;
+        let mut reader_stuck = false;
+        let mut proof = None;
+        let (first_at, read_res) = match first_response {
+            Some(response) => response,
+            None => match responses_rx.recv_timeout(reader_wait) {
+                Ok(response) => {
+                    log::info!("server response received {:?}", response.1);
+                    response
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    reader_stuck = true;
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "response reader stuck");
+                    (std::time::Instant::now(), Err(e))
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    panic!("receiver thread panicked")
+                }
+            },
+        };
+        let mut received = u32::from(read_res.is_ok());
+        let (mut last_at, mut max_gap) = (first_at, std::time::Duration::default());
+        while !reader_stuck {
+            let (at, res) = match responses_rx.recv_timeout(reader_wait) {
+                Ok(response) => response,
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    reader_stuck = true;
+                    break;
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+            };
+            if let Err(e) = res {
+                log::info!("reading response #{} failed: {:?}", received + 1, e);
+                break;
+            }
+            received += 1;
+            max_gap = std::cmp::max(max_gap, at - last_at);
+            last_at = at;
+        }
+        if reader_stuck {
+            log::warn!(
+                "response reader still blocked after {:?}, abandoning it",
+                reader_wait
+            );
+            // lets the abandoned thread's read return instead of lingering
+            let _ = reader_unblocker.shutdown(net::Shutdown::Both);
+        } else {
+            let (channel_full, reader_proof) = server_response_reader
+                .join()
+                .expect("receiver thread panicked");
+            proof = reader_proof;
+            if self.responses > 1 {
+                let elapsed = last_at - first_at;
+                log::info!(
+                    "received {} of {} responses in {:?} ({:.0} responses/s), max gap {:?}, channel full {} times",
+                    received,
+                    self.responses,
+                    elapsed,
+                    f64::from(received) / elapsed.as_secs_f64(),
+                    max_gap,
+                    channel_full
+                );
+            }
+        }
+        drop(buffered_conn);
+        close_conn(conn, self.io_uring);
+        Exchange {
+            read_res,
+            read_at: first_at,
+            write_err: written.write_err,
+            odd_write_failed: written.odd_write_failed,
+            odd_written_at: written.odd_written_at,
+            reader_stuck,
+            sent: written.sent,
+            proof,
+        }
+    }
+
+    /// the connection as the number stream is written to it, through io_uring with `--io-uring`
+    fn number_writer<'a>(&self, conn: &'a TcpStream) -> Box<dyn Write + Send + 'a> {
+        #[cfg(feature = "io-uring")]
+        {
+            if self.io_uring {
+                let ring_conn = uring::Conn::new(conn).expect("cannot set up io_uring");
+                return Box::new(SpinOnWouldBlock(ring_conn));
+            }
+        }
+        if self.write_timeout.is_some() {
+            // a blocking socket's write timeout surfaces as WouldBlock, don't spin on it
+            return Box::new(conn);
+        }
+        Box::new(SpinOnWouldBlock(conn))
+    }
+
+    /// interleave sending the request stream and reading the response on this
+    /// thread, using a nonblocking socket and poll(2)
+    #[cfg(unix)]
+    fn exchange_single_thread(&self, conn: TcpStream, payload: Option<&[u32]>) -> Exchange {
+        use std::os::unix::io::AsRawFd;
+
+        conn.set_nonblocking(true).expect("cannot set nonblocking");
+
+        // Numbers are encoded into `pending` and written out as the socket permits.
+        let pending = Rc::new(RefCell::new(Vec::new()));
+        let mut encoder = self.compress.writer(SharedBuf(pending.clone()));
+        let count = self.stream_len(payload);
+        let mut next = 0;
+        let mut all_encoded = false;
+        let mut request_flushed = false;
+        // bytes of `pending` up to and including the odd number, while unsent
+        let mut odd_unsent: Option<usize> = None;
+        let mut odd_written_at = None;
+        let mut write_err: Option<(std::time::Instant, io::Error)> = None;
+        let mut odd_write_failed = false;
+        // (bytes encoded, numbers they complete) where the encoder's output is
+        // known to hold whole numbers: after each one uncompressed, else at flushes
+        let mut boundaries = std::collections::VecDeque::<(usize, u32)>::new();
+        let mut written = 0;
+        let mut sent = 0;
+
+        let mut response = [0_u8; 4];
+        let mut received = 0;
+        let read_res = loop {
+            while write_err.is_none() && !all_encoded && pending.borrow().len() < 1 << 16 {
+                if next == count {
+                    encoder.flush().expect("encoding to memory cannot fail");
+                    boundaries.push_back((written + pending.borrow().len(), count));
+                    all_encoded = true;
+                    break;
+                }
+                let num = self.stream_number(next, payload);
+                self.framing
+                    .write_message(&mut encoder, self.endian, self.word_size, num)
+                    .expect("encoding to memory cannot fail");
+                let mut flushed = matches!(self.compress, Compress::None);
+                if !request_flushed && num % 2 == 1 {
+                    encoder.flush().expect("encoding to memory cannot fail");
+                    request_flushed = true;
+                    flushed = true;
+                    odd_unsent = Some(pending.borrow().len());
+                }
+                next += 1;
+                if flushed {
+                    boundaries.push_back((written + pending.borrow().len(), next));
+                }
+            }
+
+            let want_write = write_err.is_none() && !pending.borrow().is_empty();
+            let mut pfd = libc::pollfd {
+                fd: conn.as_raw_fd(),
+                events: libc::POLLIN | if want_write { libc::POLLOUT } else { 0 },
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, -1) } < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break Err(e);
+            }
+
+            if pfd.revents & (libc::POLLIN | libc::POLLERR | libc::POLLHUP) != 0 {
+                match (&conn).read(&mut response[received..]) {
+                    Ok(0) => {
+                        break Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "failed to fill whole buffer",
+                        ))
+                    }
+                    Ok(n) => {
+                        received += n;
+                        if received == response.len() {
+                            break Ok(self.endian.read_u32(&response[..]));
+                        }
+                    }
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => break Err(e),
+                }
+            }
+
+            if want_write && pfd.revents & (libc::POLLOUT | libc::POLLERR | libc::POLLHUP) != 0 {
+                let mut pending = pending.borrow_mut();
+                match (&conn).write(&pending) {
+                    Ok(n) => {
+                        pending.drain(..n);
+                        written += n;
+                        while let Some(&(end, numbers)) = boundaries.front() {
+                            if end > written {
+                                break;
+                            }
+                            sent = numbers;
+                            boundaries.pop_front();
+                        }
+                        odd_unsent = match odd_unsent {
+                            Some(unsent) if unsent <= n => {
+                                odd_written_at = Some(std::time::Instant::now());
+                                None
+                            }
+                            unsent => unsent.map(|unsent| unsent - n),
+                        };
+                    }
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::info!("stop sending numbers");
+                        write_err = Some((std::time::Instant::now(), e));
+                        odd_write_failed = odd_unsent.is_some();
+                    }
+                }
+            }
+        };
+        log::info!("server response received, stopping sender {:?}", read_res);
+        Exchange {
+            read_res,
+            read_at: std::time::Instant::now(),
+            write_err,
+            odd_write_failed,
+            odd_written_at,
+            reader_stuck: false,
+            sent,
+            proof: None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn exchange_single_thread(&self, _conn: TcpStream, _payload: Option<&[u32]>) -> Exchange {
+        let e = io::Error::new(io::ErrorKind::Other, "--single-thread requires poll(2)");
+        Exchange {
+            read_res: Err(e),
+            read_at: std::time::Instant::now(),
+            write_err: None,
+            odd_write_failed: false,
+            odd_written_at: None,
+            reader_stuck: false,
+            sent: 0,
+            proof: None,
+        }
+    }
+
+    /// send the request stream from this thread while a tokio task reads the response
+    ///
+    /// Mirrors `exchange_threaded` with a single response.
+    #[cfg(feature = "tokio")]
+    fn exchange_tokio(&self, conn: TcpStream, payload: Option<&[u32]>) -> Exchange {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        tokio_runtime().block_on(async {
+            conn.set_nonblocking(true).expect("cannot set nonblocking");
+            let conn = tokio::net::TcpStream::from_std(conn)
+                .expect("cannot register connection with tokio");
+            let (mut rd, wr) = conn.into_split();
+
+            let endian = self.endian;
+            let read_timeout = *self.read_timeout;
+            let len = if self.verify_sequence { 12 } else { 4 };
+            let mut reader = tokio::spawn(async move {
+                let mut buf = [0_u8; 12];
+                let res = match tokio::time::timeout(read_timeout, rd.read_exact(&mut buf[..len]))
+                    .await
+                {
+                    Ok(res) => res.map(|_| endian.read_u32(&buf[..4])),
+                    Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out")),
+                };
+                let proof = match res {
+                    Ok(_) if len > 4 => SequenceProof::decode(endian, &buf[4..len]),
+                    _ => None,
+                };
+                (std::time::Instant::now(), res, proof, rd)
+            });
+
+            let mut wr = tokio::io::BufWriter::new(wr);
+            let mut write_err: Option<(std::time::Instant, io::Error)> = None;
+            let mut request_flushed = false;
+            let mut odd_written_at = None;
+            let mut odd_write_failed = false;
+            let mut sent = 0;
+            let stream_len = self.stream_len(payload);
+            let mut batch = Vec::new();
+            for batch_start in (0..stream_len).step_by(self.batch_size as usize) {
+                if reader.is_finished() && !self.send_after_response {
+                    log::info!("server response received, stopping sender");
+                    break;
+                }
+
+                let batch_end =
+                    std::cmp::min(batch_start.saturating_add(self.batch_size), stream_len);
+                let batch_has_request =
+                    self.encode_batch(&mut batch, batch_start..batch_end, payload, request_flushed);
+
+                let mut write_res = wr.write_all(&batch).await;
+                if (batch_has_request || self.batch_size > 1) && write_res.is_ok() {
+                    write_res = wr.flush().await;
+                }
+                if batch_has_request && write_res.is_ok() {
+                    request_flushed = true;
+                    odd_written_at = Some(std::time::Instant::now());
+                }
+                if let Err(e) = write_res {
+                    write_err = Some((std::time::Instant::now(), e));
+                    odd_write_failed = batch_has_request;
+                    break;
+                }
+                sent = batch_end;
+            }
+            if write_err.is_none() {
+                write_err = wr
+                    .flush()
+                    .await
+                    .err()
+                    .map(|e| (std::time::Instant::now(), e));
+            }
+
+            let reader_wait = read_timeout + READER_GRACE;
+            let (read_at, read_res, proof, reader_stuck) =
+                match tokio::time::timeout(reader_wait, &mut reader).await {
+                    Ok(Ok((read_at, read_res, proof, rd))) => {
+                        log::info!("server response received {:?}", read_res);
+                        // dropping the split write half on its own would shut it down
+                        let conn = rd
+                            .reunite(wr.into_inner())
+                            .expect("halves of one connection");
+                        drop(conn);
+                        (read_at, read_res, proof, false)
+                    }
+                    Ok(Err(e)) => panic!("receiver task panicked: {:?}", e),
+                    Err(_) => {
+                        log::warn!(
+                            "response reader still blocked after {:?}, abandoning it",
+                            reader_wait
+                        );
+                        reader.abort();
+                        let e = io::Error::new(io::ErrorKind::TimedOut, "response reader stuck");
+                        (std::time::Instant::now(), Err(e), None, true)
+                    }
+                };
+            Exchange {
+                read_res,
+                read_at,
+                write_err,
+                odd_write_failed,
+                odd_written_at,
+                reader_stuck,
+                sent,
+                proof,
+            }
+        })
+    }
+
+    /// `payload` replaces the generated number stream if given
+    fn single_run(&self, payload: Option<&[u32]>) -> RunRecord {
+        if let Transport::Unix = self.transport {
+            return self.single_run_unix(payload);
+        }
+        match self.connect() {
+            Ok((conn, connect)) => self.run_conn(conn, connect, payload),
+            Err((e, connect)) => Self::connect_failed(e, connect),
+        }
+    }
+
+    /// `single_run` over the Unix domain socket at `--server`
+    fn single_run_unix(&self, payload: Option<&[u32]>) -> RunRecord {
+        log::info!("connecting to {:?}", self.server);
+        let connect_start = std::time::Instant::now();
+        let conn = unix_addr(&self.server).and_then(|addr| UnixStream::connect_addr(&addr));
+        let connect = connect_start.elapsed();
+        let conn = match conn.and_then(|conn| {
+            conn.set_write_timeout(self.write_timeout.map(Into::into))?;
+            Ok(conn)
+        }) {
+            Ok(conn) => conn,
+            Err(e) => return Self::connect_failed(e, connect),
+        };
+        log::info!("connected {:?}", conn);
+        let started_at = std::time::Instant::now();
+        let exchange = self.exchange_unix(&conn, payload);
+        let fin_expected = exchange.read_res.is_ok() && !exchange.reader_stuck;
+        let mut record = self.record_exchange(exchange, started_at, connect, None, None, payload);
+        if (self.passive_close || self.classify_teardown) && fin_expected {
+            self.observe_peer_teardown(&conn, &mut record);
+        }
+        let closing = std::time::Instant::now();
+        if let Err(e) = self.teardown(&conn) {
+            log::warn!("teardown {} failed: {:?}", self.teardown_mode, e);
+        }
+        drop(conn);
+        record.close = Some(closing.elapsed());
+        record
+    }
+
+    /// record of a run whose connection could not be established
+    fn connect_failed(e: io::Error, connect: std::time::Duration) -> RunRecord {
+        log::warn!("cannot connect: {:?}", e);
+        RunRecord {
+            result: match e.kind() {
+                io::ErrorKind::TimedOut => SingleRunResult::ConnectTimeout,
+                _ => SingleRunResult::ConnectError((&e).into()),
+            },
+            connect,
+            local_addr: None,
+            peer_addr: None,
+            odd_write_to_response: None,
+            response_after: None,
+            write_error_after: None,
+            close: None,
+            bytes_written: 0,
+            peer_fin: None,
+            peer_teardown: None,
+            tls_eof: None,
+            socket_states: None,
+            phase: Some(Phase::Connect),
+            sent: None,
+            proof: None,
+        }
+    }
+
+    /// read until the server ends the connection, returning how it did and
+    /// how long after the exchange
+    ///
+    /// The exchange is over, so this blocks with `--read-timeout` even on a
+    /// connection that was nonblocking during the exchange.
+    fn classify_peer_teardown<S: StreamSocket>(
+        &self,
+        conn: &S,
+    ) -> (PeerTeardown, std::time::Duration)
+    where
+        for<'a> &'a S: Read,
+    {
+        let wait_start = std::time::Instant::now();
+        let mut bytecount = 0;
+        let res = conn
+            .set_nonblocking(false)
+            .and_then(|()| conn.set_read_timeout(Some(self.read_timeout.into())))
+            .and_then(|()| {
+                let mut buf = vec![0_u8; 1 << 15];
+                loop {
+                    match (&*conn).read(&mut buf)? {
+                        0 => return Ok(()),
+                        n => bytecount += n,
+                    }
+                }
+            });
+        let after = wait_start.elapsed();
+        match res {
+            Ok(()) => {
+                log::info!(
+                    "server FIN observed {:?} after the exchange, {} bytes before it, closing",
+                    after,
+                    bytecount
+                );
+                (PeerTeardown::Fin, after)
+            }
+            Err(e) => {
+                log::warn!(
+                    "no server FIN before closing: {:?} after {:?}, {} bytes before it",
+                    e,
+                    after,
+                    bytecount
+                );
+                let teardown = match e.kind() {
+                    io::ErrorKind::ConnectionReset => PeerTeardown::Reset,
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => PeerTeardown::Hang,
+                    kind => PeerTeardown::OtherError(kind),
+                };
+                (teardown, after)
+            }
+        }
+    }
+
+    /// read until the server's FIN, returning whether it arrived
+    fn await_peer_fin<S: StreamSocket>(&self, conn: &S) -> bool
+    where
+        for<'a> &'a S: Read,
+    {
+        self.classify_peer_teardown(conn).0 == PeerTeardown::Fin
+    }
+
+    /// with `--passive-close` or `--classify-teardown`, wait for the server to
+    /// end the connection after the response
+    fn observe_peer_teardown<S: StreamSocket>(&self, conn: &S, record: &mut RunRecord)
+    where
+        for<'a> &'a S: Read,
+    {
+        let (teardown, after) = self.classify_peer_teardown(conn);
+        if self.passive_close {
+            record.peer_fin = Some(teardown == PeerTeardown::Fin);
+        }
+        if self.classify_teardown {
+            record.peer_teardown = Some((teardown, after));
+        }
+    }
+
+    /// tear down the connection with `--teardown-mode`, before the implicit close
+    ///
+    /// Waiting for the server's FIN gives up after `--read-timeout`.
+    fn teardown<S: StreamSocket>(&self, conn: &S) -> io::Result<()>
+    where
+        for<'a> &'a S: Read,
+    {
+        let linger_zero = || {
+            log::info!("setting linger to zero");
+            conn.set_linger(Some(std::time::Duration::from_secs(0)))
+        };
+        match self.teardown_mode {
+            TeardownMode::CloseImmediately => {}
+            TeardownMode::ResetImmediately => linger_zero()?,
+            TeardownMode::DrainThenClose => {
+                self.await_peer_fin(conn);
+            }
+            TeardownMode::ShutdownWriteThenDrain => {
+                conn.shutdown(net::Shutdown::Write)?;
+                self.await_peer_fin(conn);
+            }
+            TeardownMode::Shutdown { half } => {
+                log::info!("shutting down {}", half);
+                conn.shutdown(half.into())?;
+            }
+            TeardownMode::SleepThenClose => spin_sleep::sleep(*self.teardown_sleep),
+            TeardownMode::ShutdownWriteWaitFinThenReset => {
+                conn.shutdown(net::Shutdown::Write)?;
+                if self.await_peer_fin(conn) {
+                    linger_zero()?;
+                }
+            }
+            TeardownMode::LingerZeroThenShutdown => {
+                linger_zero()?;
+                conn.shutdown(net::Shutdown::Both)?;
+            }
+            TeardownMode::DoubleShutdownWrite => {
+                conn.shutdown(net::Shutdown::Write)?;
+                match conn.shutdown(net::Shutdown::Write) {
+                    Ok(()) => log::info!("second shutdown write: Ok, a no-op"),
+                    Err(e) => log::info!("second shutdown write: error {:?}: {}", e.kind(), e),
+                }
+            }
+            TeardownMode::CloseAfterNEvenNumbers | TeardownMode::FloodThenClose => {
+                unreachable!("rejected as --teardown-mode by Client::run")
+            }
+        }
+        log::info!("implicit drop & close of the connection");
+        Ok(())
+    }
+
+    /// the first resolved server address of the family chosen by `-4`/`-6` or `--bind`
+    fn server_addr(&self) -> io::Result<net::SocketAddr> {
+        let want_v6 = if self.ipv4 || self.ipv6 {
+            Some(self.ipv6)
+        } else if let Some(bind) = &self.bind {
+            net::ToSocketAddrs::to_socket_addrs(bind)?
+                .next()
+                .map(|addr| addr.is_ipv6())
+        } else {
+            None
+        };
+        net::ToSocketAddrs::to_socket_addrs(&self.server)?
+            .find(|addr| want_v6.is_none_or(|v6| addr.is_ipv6() == v6))
+            .ok_or_else(|| {
+                let family = if want_v6 == Some(true) {
+                    "IPv6"
+                } else {
+                    "IPv4"
+                };
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("{} has no {} address", self.server, family),
+                )
+            })
+    }
+
+    /// connect to the server, returning the connection and how long connecting took
+    fn connect(
+        &self,
+    ) -> Result<(TcpStream, std::time::Duration), (io::Error, std::time::Duration)> {
+        log::info!("connecting to {:?}", self.server);
+        let connect_start = std::time::Instant::now();
+
+        // Connect to the server
+        let conn = self.server_addr().and_then(|addr| {
+            let socket = tcp_socket(addr)?;
+            if !self.no_reuse_port {
+                Self::set_reuse_port(&socket);
+            }
+            if let Some(bind) = &self.bind {
+                let bind = net::ToSocketAddrs::to_socket_addrs(bind)
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .expect("cannot resolve specified bind address");
+                socket
+                    .bind(&bind.into())
+                    .expect("cannot bind to specified address");
+            }
+            self.set_retransmission_opts(&socket);
+            self.keepalive.apply(&socket)?;
+            set_buffer_sizes(&socket, self.sndbuf, self.rcvbuf)?;
+            if let Some(timeout) = self.tcp_user_timeout {
+                set_user_timeout(&socket, *timeout)?;
+            }
+            if let Some(linger) = self.linger {
+                socket.set_linger(Some(*linger))?;
+                log::info!("set SO_LINGER to {}", linger);
+            }
+            for opt in &self.sockopts {
+                opt.apply(&socket)?;
+            }
+            match self.connect_timeout {
+                Some(timeout) => socket.connect_timeout(&addr.into(), *timeout)?,
+                None => socket.connect(&addr.into())?,
+            }
+            Ok(TcpStream::from(socket))
+        });
+        let connect = connect_start.elapsed();
+        let conn = conn.map_err(|e| (e, connect))?;
+        let _span = ConnLogSpan::enter(conn.local_addr(), conn.peer_addr());
+        log::info!("connected {:?}", conn);
+        if let Err(e) = conn
+            .set_write_timeout(self.write_timeout.map(Into::into))
+            .and_then(|()| conn.set_nodelay(self.nodelay))
+        {
+            return Err((e, connect));
+        }
+        Ok((conn, connect))
+    }
+
+    /// run the request protocol on a fresh connection and categorize what happened
+    fn run_conn(
+        &self,
+        conn: TcpStream,
+        connect: std::time::Duration,
+        payload: Option<&[u32]>,
+    ) -> RunRecord {
+        let (local_addr, peer_addr) = (conn.local_addr().ok(), conn.peer_addr().ok());
+        let _span = ConnLogSpan::enter(conn.local_addr(), conn.peer_addr());
+        if self.nonblocking {
+            // also affects the reader thread's clone of the connection
+            conn.set_nonblocking(true).expect("cannot set nonblocking");
+        }
+        if self.dump_sockopts {
+            dump_sockopts(&conn);
+        }
+        let teardown_conn = match self.teardown_mode {
+            TeardownMode::CloseImmediately if !self.tcp_info => None,
+            _ => Some(conn.try_clone().expect("cannot clone connection handle")),
+        };
+        let fin_conn = if self.passive_close || self.classify_teardown {
+            Some(conn.try_clone().expect("cannot clone connection handle"))
+        } else {
+            None
+        };
+
+        let started_at = std::time::Instant::now();
+        let (exchange, tls_eof) = match self.backend {
+            #[cfg(feature = "tls")]
+            Backend::Std if self.tls => self.exchange_tls(conn, payload),
+            #[cfg(not(feature = "tls"))]
+            Backend::Std if self.tls => unreachable!("rejected by Client::run"),
+            Backend::Std if self.single_thread => {
+                (self.exchange_single_thread(conn, payload), None)
+            }
+            Backend::Std => (self.exchange_threaded(conn, payload), None),
+            #[cfg(feature = "tokio")]
+            Backend::Tokio => (self.exchange_tokio(conn, payload), None),
+            #[cfg(not(feature = "tokio"))]
+            Backend::Tokio => unreachable!("rejected by Client::run"),
+        };
+        // Only a connection that delivered the response is expected to end with a FIN.
+        let fin_expected = exchange.read_res.is_ok() && !exchange.reader_stuck;
+        let mut record = self.record_exchange(
+            exchange, started_at, connect, local_addr, peer_addr, payload,
+        );
+        record.tls_eof = tls_eof;
+        if let Some(conn) = fin_conn.filter(|_| fin_expected) {
+            self.observe_peer_teardown(&conn, &mut record);
+        }
+        if let Some(conn) = teardown_conn {
+            if self.tcp_info {
+                log_tcp_info(&conn, "before teardown");
+            }
+            let closing = std::time::Instant::now();
+            if let Err(e) = self.teardown(&conn) {
+                log::warn!("teardown {} failed: {:?}", self.teardown_mode, e);
+            }
+            let mut close = closing.elapsed();
+            if self.tcp_info {
+                log_tcp_info(&conn, "after teardown");
+            }
+            let closing = std::time::Instant::now();
+            close_conn(conn, self.io_uring);
+            close += closing.elapsed();
+            record.close = Some(close);
+        }
+        capture::log_timeline(local_addr, peer_addr);
+        if self.report_socket_states {
+            record.socket_states = Self::socket_states(local_addr, peer_addr);
+        }
+        record
+    }
+
+    /// the kernel state of both ends of the connection, `gone` for a socket it no longer has
+    fn socket_states(
+        local: Option<net::SocketAddr>,
+        peer: Option<net::SocketAddr>,
+    ) -> Option<(&'static str, &'static str)> {
+        let (local, peer) = (sock_diag::canonical(local?), sock_diag::canonical(peer?));
+        let sockets = match sock_diag::tcp_sockets() {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                log::warn!("cannot query socket states: {:?}", e);
+                return None;
+            }
+        };
+        let state = |local, peer| {
+            sockets
+                .iter()
+                .find(|s| s.local == local && s.peer == peer)
+                .map_or("gone", |s| tcp_state_name(s.state))
+        };
+        let states = (state(local, peer), state(peer, local));
+        log::info!(
+            "socket states after close: ours {}, server's {}",
+            states.0,
+            states.1
+        );
+        Some(states)
+    }
+
+    /// TIME_WAIT sockets between us and the server, ours and the server's
+    fn count_time_wait(&self) -> Option<(usize, usize)> {
+        const TIME_WAIT: u8 = 0x06;
+        let server = match self.server_addr() {
+            Ok(addr) => sock_diag::canonical(addr),
+            Err(e) => {
+                log::warn!("cannot resolve server address: {:?}", e);
+                return None;
+            }
+        };
+        let sockets = match sock_diag::tcp_sockets() {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                log::warn!("cannot query socket states: {:?}", e);
+                return None;
+            }
+        };
+        let time_wait = sockets.iter().filter(|s| s.state == TIME_WAIT);
+        let (ours, servers) = time_wait.fold((0, 0), |(ours, servers), s| {
+            (
+                ours + usize::from(s.peer == server),
+                servers + usize::from(s.local == server),
+            )
+        });
+        Some((ours, servers))
+    }
+
+    /// categorize what happened in `exchange`, leaving `peer_fin` to the caller
+    fn record_exchange(
+        &self,
+        exchange: Exchange,
+        started_at: std::time::Instant,
+        connect: std::time::Duration,
+        local_addr: Option<net::SocketAddr>,
+        peer_addr: Option<net::SocketAddr>,
+        payload: Option<&[u32]>,
+    ) -> RunRecord {
+        let Exchange {
+            read_res,
+            read_at,
+            write_err,
+            odd_write_failed,
+            odd_written_at,
+            reader_stuck,
+            sent,
+            proof,
+        } = exchange;
+        // negative if the response was read before the odd number's write returned
+        let odd_write_to_response = match (&read_res, odd_written_at) {
+            (Ok(_), Some(written_at)) => {
+                let delta = if read_at >= written_at {
+                    (read_at - written_at).as_nanos() as i64
+                } else {
+                    -((written_at - read_at).as_nanos() as i64)
+                };
+                log::debug!("request RTT: {}ns", delta);
+                Some(delta)
+            }
+            _ => None,
+        };
+        let mismatch = match (&read_res, self.expected_response(payload)) {
+            (Ok(num), Some(expected)) if *num != expected => {
+                if num.swap_bytes() == expected {
+                    log::warn!(
+                        "response {} is {} byte-swapped, does the server's --endian match?",
+                        num,
+                        expected
+                    );
+                } else {
+                    log::warn!("response {} does not match {}", num, expected);
+                }
+                true
+            }
+            _ => false,
+        };
+        match proof {
+            Some(proof) => log::info!(
+                "server proved receipt of the first {} of {} sent numbers, teardown mode {}",
+                proof.in_sequence,
+                sent,
+                proof.mode
+            ),
+            None if self.verify_sequence => {
+                log::info!("no proof of receipt for {} sent numbers", sent)
+            }
+            None => {}
+        }
+        let response_after = read_res
+            .as_ref()
+            .ok()
+            .map(|_| read_at.saturating_duration_since(started_at));
+        let write_error_after = write_err
+            .as_ref()
+            .map(|(write_at, _)| write_at.saturating_duration_since(started_at));
+        let read_err: Option<io::Error> = read_res.map(|_num| ()).err();
+
+        // Where the first observed failure happened: the reader notices either
+        // before or after the odd number went out, the writer knows what it wrote.
+        let read_phase = match odd_written_at {
+            Some(written_at) if written_at <= read_at => Phase::ResponseRead,
+            _ => Phase::EvenSend,
+        };
+        let write_phase = |write_at: std::time::Instant| match odd_written_at {
+            Some(written_at) if written_at <= write_at => Phase::ResponseRead,
+            _ if odd_write_failed => Phase::OddSend,
+            _ => Phase::EvenSend,
+        };
+        let phase = match (&read_err, &write_err) {
+            (None, None) => None,
+            (Some(_), None) => Some(read_phase),
+            (None, Some((write_at, _))) => Some(write_phase(*write_at)),
+            (Some(_), Some((write_at, _))) if read_at <= *write_at => Some(read_phase),
+            (Some(_), Some((write_at, _))) => Some(write_phase(*write_at)),
+        };
+
+        // Categorize what we observed in this run (used for statistics)
+        // Socket timeouts surface as `WouldBlock`, our own deadlines as `TimedOut`.
+        let is_timeout = |e: &io::Error| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        };
+        let result = match (read_err, write_err) {
+            _ if reader_stuck => SingleRunResult::ReaderStuck,
+            (None, None) if mismatch => SingleRunResult::ResponseMismatch,
+            (None, None) => SingleRunResult::ResponseCorrect,
+            (Some(e), None) if is_timeout(&e) => SingleRunResult::ReadTimeout,
+            (Some(e), None) => SingleRunResult::ReadResponseError((&e).into()),
+            (None, Some((write_at, e))) if self.send_after_response && write_at > read_at => {
+                SingleRunResult::PostResponseWriteError((&e).into())
+            }
+            (None, Some((_, e))) if is_timeout(&e) => SingleRunResult::WriteTimeout,
+            (None, Some((_, e))) => SingleRunResult::WriteNumberError((&e).into()),
+            // the server stopped reading, no response is coming either
+            (Some(read), Some((write_at, write)))
+                if is_timeout(&write) && is_timeout(&read) && write_at <= read_at =>
+            {
+                SingleRunResult::WriteTimeout
+            }
+            (Some(read), Some((write_at, write))) => SingleRunResult::BothErr {
+                read: (&read).into(),
+                write: (&write).into(),
+                order: if read_at <= write_at {
+                    ErrorOrder::ReadFirst
+                } else {
+                    ErrorOrder::WriteFirst
+                },
+            },
+        };
+        RunRecord {
+            result,
+            connect,
+            local_addr,
+            peer_addr,
+            odd_write_to_response,
+            response_after,
+            write_error_after,
+            close: None,
+            bytes_written: self.stream_bytes(sent, payload),
+            peer_fin: None,
+            peer_teardown: None,
+            tls_eof: None,
+            socket_states: None,
+            phase,
+            sent: if self.verify_sequence {
+                Some(sent)
+            } else {
+                None
+            },
+            proof,
+        }
+    }
+}
+
+impl Client {
+    /// the client as the command line configures it from just the server address
+    pub fn defaults(server: net::SocketAddr) -> Client {
+        Client::from_iter_safe(vec!["client".to_string(), server.to_string()])
+            .expect("the client's defaults parse")
+    }
+}
+
+/// Configures a client and runs the request protocol against a server.
+///
+/// Options not set here take the command line's defaults.
+pub struct ClientBuilder {
+    client: Client,
+}
+
+impl ClientBuilder {
+    pub fn new(server: net::SocketAddr) -> Self {
+        ClientBuilder {
+            client: Client::defaults(server),
+        }
+    }
+
+    /// number of even numbers sent before the odd one
+    pub fn send_count(mut self, count: u32) -> Self {
+        self.client.send_count = count;
+        self
+    }
+
+    /// send the odd number first, before any even ones
+    pub fn odd_first(mut self, odd_first: bool) -> Self {
+        self.client.odd_first = odd_first;
+        self
+    }
+
+    /// keep sending even numbers after the response arrived, until writing fails
+    pub fn send_after_response(mut self, send_after_response: bool) -> Self {
+        self.client.send_after_response = send_after_response;
+        self
+    }
+
+    /// give up waiting for the response after this long
+    pub fn read_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.client.read_timeout = timeout.into();
+        self
+    }
+
+    /// give up connecting after this long
+    pub fn connect_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.client.connect_timeout = Some(timeout.into());
+        self
+    }
+
+    /// give up on a write blocked for this long
+    pub fn write_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.client.write_timeout = Some(timeout.into());
+        self
+    }
+
+    /// disable Nagle's algorithm
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.client.nodelay = nodelay;
+        self
+    }
+
+    /// SO_LINGER of the connection, `Some(Duration::ZERO)` makes every close send an RST
+    pub fn linger(mut self, linger: Option<std::time::Duration>) -> Self {
+        self.client.linger = linger.map(Into::into);
+        self
+    }
+
+    /// SO_SNDBUF and SO_RCVBUF in bytes, `None` for the system default
+    pub fn buffer_sizes(mut self, sndbuf: Option<u32>, rcvbuf: Option<u32>) -> Self {
+        self.client.sndbuf = sndbuf;
+        self.client.rcvbuf = rcvbuf;
+        self
+    }
+
+    /// connect, run the request protocol once and categorize what happened
+    pub fn run_once(&self) -> SingleRunResult {
+        self.client.single_run(None).result
+    }
+
+    /// `run_once` `times` in a row, counting the results
+    pub fn run(&self, times: usize) -> HashMap<SingleRunResult, usize> {
+        let mut runs = HashMap::new();
+        for _ in 0..times {
+            *runs.entry(self.run_once()).or_insert(0) += 1;
+        }
+        runs
+    }
+}
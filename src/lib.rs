@@ -0,0 +1,1646 @@
+//! Experiments with the ways a TCP server can tear down a connection, and
+//! what the client observes for each.
+//!
+//! The `tcpteardown` binary is a thin command line around this crate. To run
+//! the experiments from other programs, e.g. their integration tests, use
+//! [`ServerBuilder`] and [`ClientBuilder`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{self, TcpStream};
+use std::os::unix::net::UnixStream;
+
+use bytes::{BigEndian, ByteOrder, LittleEndian};
+use failure::ResultExt;
+use structopt::StructOpt;
+#[macro_use]
+extern crate strum_macros;
+use strum::IntoEnumIterator;
+
+/// macro used to measure & log the duration of a given expression
+///
+/// If a place expression of type `Option<Duration>` is passed as the second
+/// argument, the measured duration is also stored there.
+macro_rules! time_and_log_debug {
+    ($name:expr, $e:expr) => {{
+        let pre = std::time::Instant::now();
+        let res = $e;
+        let post = std::time::Instant::now() - pre;
+        log::debug!("{:?}: {:?}", $name, post);
+        res
+    }};
+    ($name:expr, $dst:expr, $e:expr) => {{
+        let pre = std::time::Instant::now();
+        let res = $e;
+        let post = std::time::Instant::now() - pre;
+        log::debug!("{:?}: {:?}", $name, post);
+        $dst = Some(post);
+        res
+    }};
+}
+
+mod capture;
+mod client;
+mod proxy;
+mod server;
+mod sock_diag;
+mod stats;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+pub use client::{ClientBuilder, ErrorOrder, RunError, SingleRunResult};
+pub use server::{RunningServer, ServerBuilder};
+pub use stats::ServerStats;
+
+use client::{Client, RunLog};
+use proxy::Proxy;
+use server::{ReportLog, Server};
+
+/// The `tcpteardown` command line.
+#[derive(StructOpt)]
+pub struct Opt {
+    #[structopt(
+        short = "q",
+        long = "quiet",
+        parse(from_occurrences),
+        help = "decrease log verbosity (stackable, ignored if RUST_LOG is set)"
+    )]
+    quiet: u8,
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        parse(from_occurrences),
+        help = "increase log verbosity (stackable, ignored if RUST_LOG is set)"
+    )]
+    verbose: u8,
+    #[structopt(subcommand)]
+    app: App,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+enum App {
+    Server(Server),
+    Client(Client),
+    Modes(Modes),
+    Bench(Bench),
+    Interactive(Interactive),
+    Experiment(Experiment),
+    Proxy(Proxy),
+}
+
+/// List the teardown modes.
+#[derive(StructOpt)]
+struct Modes {
+    #[structopt(
+        long = "format",
+        help = "`text` for one name per line, or `json` for an array with each mode's syscall sequence and the server options it reads",
+        default_value = "text"
+    )]
+    format: ModesFormat,
+}
+
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum ModesFormat {
+    Text,
+    Json,
+}
+
+impl Modes {
+    fn run(&self) -> Result<(), failure::Error> {
+        match self.format {
+            ModesFormat::Text => TeardownMode::all().for_each(|e| println!("{}", e)),
+            ModesFormat::Json => {
+                let modes = TeardownMode::all()
+                    .map(|mode| {
+                        serde_json::json!({
+                            "name": mode.to_string(),
+                            "code": mode.code(),
+                            "syscalls": mode.syscalls(),
+                            "params": mode.params(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::to_string_pretty(&modes)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the server does to a connection after writing the response.
+///
+/// Parsed from and displayed as the names `tcpteardown modes` lists.
+#[derive(Clone, Copy, EnumIter)]
+pub enum TeardownMode {
+    CloseImmediately,
+    /// Sets linger to zero before the drop, so the close sends an RST instead of a FIN.
+    ResetImmediately,
+    DrainThenClose,
+    ShutdownWriteThenDrain,
+    /// Shuts down `half` of the connection, then closes it.
+    /// Written as `shutdown:read`, `shutdown:write` or `shutdown:both`.
+    Shutdown {
+        half: ShutdownHalf,
+    },
+    SleepThenClose,
+    ShutdownWriteWaitFinThenReset,
+    CloseAfterNEvenNumbers,
+    FloodThenClose,
+    /// Linger only applies to close, so the shutdown still sends a FIN,
+    /// unlike a plain drop with linger zero, which sends an RST right away.
+    /// The RST follows on the implicit drop.
+    LingerZeroThenShutdown,
+    /// Logs whether the second `shutdown(Write)` is a no-op or an error.
+    DoubleShutdownWrite,
+}
+
+impl TeardownMode {
+    /// every mode, with `Shutdown` once per half
+    pub fn all() -> impl Iterator<Item = TeardownMode> {
+        TeardownMode::iter().flat_map(|mode| match mode {
+            TeardownMode::Shutdown { .. } => ShutdownHalf::iter()
+                .map(|half| TeardownMode::Shutdown { half })
+                .collect(),
+            mode => vec![mode],
+        })
+    }
+
+    /// how `--verify-sequence` responses name the mode
+    ///
+    /// Part of the wire protocol: never reuse or renumber a code, a new mode
+    /// gets the next free one.
+    fn code(self) -> u32 {
+        match self {
+            TeardownMode::CloseImmediately => 0,
+            TeardownMode::ResetImmediately => 1,
+            TeardownMode::DrainThenClose => 2,
+            TeardownMode::ShutdownWriteThenDrain => 3,
+            TeardownMode::Shutdown {
+                half: ShutdownHalf::Read,
+            } => 4,
+            TeardownMode::Shutdown {
+                half: ShutdownHalf::Write,
+            } => 5,
+            TeardownMode::Shutdown {
+                half: ShutdownHalf::Both,
+            } => 6,
+            TeardownMode::SleepThenClose => 7,
+            TeardownMode::ShutdownWriteWaitFinThenReset => 8,
+            TeardownMode::CloseAfterNEvenNumbers => 9,
+            TeardownMode::FloodThenClose => 10,
+            TeardownMode::LingerZeroThenShutdown => 11,
+            TeardownMode::DoubleShutdownWrite => 12,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<TeardownMode> {
+        TeardownMode::all().find(|mode| mode.code() == code)
+    }
+
+    /// what the server does to the socket after writing the response, as
+    /// `Server::teardown` does it; a `--flag` stands for the mode it names
+    fn syscalls(self) -> Vec<String> {
+        const LINGER_ZERO: &str = "setsockopt(SO_LINGER, 0)";
+        const DRAIN: &str = "read until EOF";
+        let syscalls: &[&str] = match self {
+            TeardownMode::CloseImmediately => &["close"],
+            TeardownMode::ResetImmediately => &[LINGER_ZERO, "close"],
+            TeardownMode::DrainThenClose => &[DRAIN, "close"],
+            TeardownMode::ShutdownWriteThenDrain => &["shutdown(SHUT_WR)", DRAIN, "close"],
+            TeardownMode::Shutdown { half } => {
+                let how = match half {
+                    ShutdownHalf::Read => "SHUT_RD",
+                    ShutdownHalf::Write => "SHUT_WR",
+                    ShutdownHalf::Both => "SHUT_RDWR",
+                };
+                return vec![format!("shutdown({})", how), "close".to_string()];
+            }
+            TeardownMode::SleepThenClose => &["sleep", "close"],
+            TeardownMode::ShutdownWriteWaitFinThenReset => {
+                &["shutdown(SHUT_WR)", DRAIN, LINGER_ZERO, "close"]
+            }
+            // without the response
+            TeardownMode::CloseAfterNEvenNumbers => &["--close-after-mode"],
+            TeardownMode::FloodThenClose => &["write --flood-bytes", "--flood-mode"],
+            TeardownMode::LingerZeroThenShutdown => &[LINGER_ZERO, "shutdown(SHUT_RDWR)", "close"],
+            TeardownMode::DoubleShutdownWrite => {
+                &["shutdown(SHUT_WR)", "shutdown(SHUT_WR)", "close"]
+            }
+        };
+        syscalls.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// the server options that change what the mode does
+    fn params(self) -> &'static [&'static str] {
+        const DRAIN: &[&str] = &["--drain-limit", "--drain-timeout", "--linger"];
+        match self {
+            TeardownMode::CloseImmediately => &["--linger"],
+            TeardownMode::ResetImmediately | TeardownMode::LingerZeroThenShutdown => &[],
+            TeardownMode::DrainThenClose | TeardownMode::ShutdownWriteThenDrain => DRAIN,
+            TeardownMode::Shutdown {
+                half: ShutdownHalf::Both,
+            } => &["--detect-simultaneous", "--linger"],
+            TeardownMode::Shutdown { .. } | TeardownMode::DoubleShutdownWrite => &["--linger"],
+            TeardownMode::SleepThenClose => &["--sleep", "--sleep-dist", "--linger"],
+            TeardownMode::ShutdownWriteWaitFinThenReset => &["--drain-limit", "--drain-timeout"],
+            TeardownMode::CloseAfterNEvenNumbers => &["--close-after", "--close-after-mode"],
+            TeardownMode::FloodThenClose => &["--flood-bytes", "--flood-mode", "--write-timeout"],
+        }
+    }
+}
+
+impl std::fmt::Display for TeardownMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            TeardownMode::CloseImmediately => "close-immediately",
+            TeardownMode::ResetImmediately => "reset-immediately",
+            TeardownMode::DrainThenClose => "drain-then-close",
+            TeardownMode::ShutdownWriteThenDrain => "shutdown-write-then-drain",
+            TeardownMode::Shutdown { half } => return write!(f, "shutdown:{}", half),
+            TeardownMode::SleepThenClose => "sleep-then-close",
+            TeardownMode::ShutdownWriteWaitFinThenReset => "shutdown-write-wait-fin-then-reset",
+            TeardownMode::CloseAfterNEvenNumbers => "close-after-n-even-numbers",
+            TeardownMode::FloodThenClose => "flood-then-close",
+            TeardownMode::LingerZeroThenShutdown => "linger-zero-then-shutdown",
+            TeardownMode::DoubleShutdownWrite => "double-shutdown-write",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for TeardownMode {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // names from before `Shutdown` took the half as a parameter
+        match s {
+            "shutdown-write-then-close" => {
+                return Ok(TeardownMode::Shutdown {
+                    half: ShutdownHalf::Write,
+                })
+            }
+            "shutdown-both-then-close" => {
+                return Ok(TeardownMode::Shutdown {
+                    half: ShutdownHalf::Both,
+                })
+            }
+            _ => {}
+        }
+        TeardownMode::all()
+            .find(|mode| mode.to_string() == s)
+            .ok_or_else(|| {
+                failure::format_err!("unknown teardown mode {:?}, see the `modes` subcommand", s)
+            })
+    }
+}
+
+/// Half of a connection to shut down in `TeardownMode::Shutdown`.
+#[derive(Clone, Copy, Default, EnumString, EnumIter, Display)]
+#[strum(serialize_all = "kebab_case")]
+pub enum ShutdownHalf {
+    Read,
+    #[default]
+    Write,
+    Both,
+}
+
+impl From<ShutdownHalf> for net::Shutdown {
+    fn from(half: ShutdownHalf) -> Self {
+        match half {
+            ShutdownHalf::Read => net::Shutdown::Read,
+            ShutdownHalf::Write => net::Shutdown::Write,
+            ShutdownHalf::Both => net::Shutdown::Both,
+        }
+    }
+}
+
+/// Core ids to pin handler threads to, parsed from `0,1,2`.
+struct CpuList(Vec<usize>);
+
+impl std::str::FromStr for CpuList {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cores = s
+            .split(',')
+            .map(|core| {
+                core.parse::<usize>()
+                    .with_context(|_| format!("invalid core id {:?}", core))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(CpuList(cores))
+    }
+}
+
+impl CpuList {
+    /// fail early on core ids this machine doesn't have
+    fn validate(&self) -> Result<(), failure::Error> {
+        let available = core_affinity::get_core_ids()
+            .ok_or_else(|| failure::format_err!("cannot determine the available cores"))?;
+        for core in &self.0 {
+            if !available.iter().any(|c| c.id == *core) {
+                failure::bail!("core {} is not available", core);
+            }
+        }
+        Ok(())
+    }
+
+    /// pin the calling thread to the `i`th core of the list, wrapping around
+    ///
+    /// The cores were validated up front, so this doesn't fail.
+    fn pin_current_thread(&self, i: usize) {
+        let id = self.0[i % self.0.len()];
+        core_affinity::set_for_current(core_affinity::CoreId { id });
+        log::info!("pinned {:?} to core {}", std::thread::current().id(), id);
+    }
+}
+
+/// A raw integer socket option from `--sockopt`, for experiments with options
+/// that have no dedicated flag.
+struct SockOpt {
+    name: String,
+    level: libc::c_int,
+    optname: libc::c_int,
+    value: libc::c_int,
+}
+
+impl SockOpt {
+    /// the level and option number of a symbolic option name
+    #[cfg(unix)]
+    fn lookup(name: &str) -> Option<(libc::c_int, libc::c_int)> {
+        use libc::*;
+
+        let opt = match name {
+            "SO_REUSEADDR" => (SOL_SOCKET, SO_REUSEADDR),
+            "SO_REUSEPORT" => (SOL_SOCKET, SO_REUSEPORT),
+            "SO_KEEPALIVE" => (SOL_SOCKET, SO_KEEPALIVE),
+            "SO_SNDBUF" => (SOL_SOCKET, SO_SNDBUF),
+            "SO_RCVBUF" => (SOL_SOCKET, SO_RCVBUF),
+            "SO_RCVLOWAT" => (SOL_SOCKET, SO_RCVLOWAT),
+            "SO_OOBINLINE" => (SOL_SOCKET, SO_OOBINLINE),
+            "SO_DONTROUTE" => (SOL_SOCKET, SO_DONTROUTE),
+            "IP_TOS" => (IPPROTO_IP, IP_TOS),
+            "IP_TTL" => (IPPROTO_IP, IP_TTL),
+            "IPV6_UNICAST_HOPS" => (IPPROTO_IPV6, IPV6_UNICAST_HOPS),
+            "IPV6_V6ONLY" => (IPPROTO_IPV6, IPV6_V6ONLY),
+            "TCP_NODELAY" => (IPPROTO_TCP, TCP_NODELAY),
+            "TCP_MAXSEG" => (IPPROTO_TCP, TCP_MAXSEG),
+            #[cfg(target_os = "linux")]
+            name => match name {
+                "SO_PRIORITY" => (SOL_SOCKET, SO_PRIORITY),
+                "SO_MARK" => (SOL_SOCKET, SO_MARK),
+                "SO_SNDBUFFORCE" => (SOL_SOCKET, SO_SNDBUFFORCE),
+                "SO_RCVBUFFORCE" => (SOL_SOCKET, SO_RCVBUFFORCE),
+                "SO_BUSY_POLL" => (SOL_SOCKET, SO_BUSY_POLL),
+                "IP_MTU_DISCOVER" => (IPPROTO_IP, IP_MTU_DISCOVER),
+                "IP_FREEBIND" => (IPPROTO_IP, IP_FREEBIND),
+                "IPV6_TCLASS" => (IPPROTO_IPV6, IPV6_TCLASS),
+                "TCP_KEEPIDLE" => (IPPROTO_TCP, TCP_KEEPIDLE),
+                "TCP_KEEPINTVL" => (IPPROTO_TCP, TCP_KEEPINTVL),
+                "TCP_KEEPCNT" => (IPPROTO_TCP, TCP_KEEPCNT),
+                "TCP_SYNCNT" => (IPPROTO_TCP, TCP_SYNCNT),
+                "TCP_USER_TIMEOUT" => (IPPROTO_TCP, TCP_USER_TIMEOUT),
+                "TCP_LINGER2" => (IPPROTO_TCP, TCP_LINGER2),
+                "TCP_DEFER_ACCEPT" => (IPPROTO_TCP, TCP_DEFER_ACCEPT),
+                "TCP_WINDOW_CLAMP" => (IPPROTO_TCP, TCP_WINDOW_CLAMP),
+                "TCP_QUICKACK" => (IPPROTO_TCP, TCP_QUICKACK),
+                "TCP_CORK" => (IPPROTO_TCP, TCP_CORK),
+                "TCP_NOTSENT_LOWAT" => (IPPROTO_TCP, TCP_NOTSENT_LOWAT),
+                "TCP_FASTOPEN" => (IPPROTO_TCP, TCP_FASTOPEN),
+                _ => return None,
+            },
+            #[cfg(not(target_os = "linux"))]
+            _ => return None,
+        };
+        Some(opt)
+    }
+
+    #[cfg(not(unix))]
+    fn lookup(_name: &str) -> Option<(libc::c_int, libc::c_int)> {
+        None
+    }
+
+    fn apply(&self, socket: &socket2::Socket) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            setsockopt_int(socket, self.level, self.optname, self.value)?;
+            log::info!("set {} to {}", self.name, self.value);
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = socket;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--sockopt is Unix only",
+            ))
+        }
+    }
+}
+
+impl std::str::FromStr for SockOpt {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| failure::format_err!("expected NAME=VALUE, got {:?}", s))?;
+        // the name is upper-cased below, so a hex level or option reads `0X`
+        let int = |s: &str| match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => libc::c_int::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        let name = name.to_ascii_uppercase();
+        let (level, optname) = match name.split_once(':') {
+            Some((level, optname)) => (
+                int(level).with_context(|_| format!("invalid level {:?}", level))?,
+                int(optname).with_context(|_| format!("invalid option {:?}", optname))?,
+            ),
+            None => Self::lookup(&name)
+                .ok_or_else(|| failure::format_err!("unknown socket option {:?}", name))?,
+        };
+        let value = int(value).with_context(|_| format!("invalid value {:?}", value))?;
+        Ok(SockOpt {
+            name,
+            level,
+            optname,
+            value,
+        })
+    }
+}
+
+impl std::fmt::Display for SockOpt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+/// How the client's request stream is split into messages.
+///
+/// The server's response is always a single raw 4-byte number.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Framing {
+    /// each message is a number
+    Numbers,
+    /// each message is a length followed by that many payload bytes, the
+    /// length takes the role of the number
+    LengthPrefixed,
+}
+
+/// What the server does with a connection before tearing it down.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Workload {
+    /// read numbers until the first odd one and send it back
+    Numbers,
+    /// send back everything the client sends, so that data is in flight in
+    /// both directions at teardown time
+    Echo,
+}
+
+/// Width of the request stream's numbers and length prefixes.
+///
+/// The numbers themselves stay 32-bit, wider words are zero-extended.
+#[derive(Clone, Copy, EnumString, Display)]
+enum WordSize {
+    #[strum(serialize = "4")]
+    U32,
+    #[strum(serialize = "8")]
+    U64,
+}
+
+/// TCP keepalive probing, for telling a crashed peer from one that closed.
+///
+/// The kernel takes the durations in whole seconds, they are rounded up.
+#[derive(StructOpt, Default)]
+struct Keepalive {
+    #[structopt(
+        long = "keepalive",
+        help = "enable TCP keepalive probing (SO_KEEPALIVE)"
+    )]
+    keepalive: bool,
+    #[structopt(
+        long = "keepalive-idle",
+        help = "idle time before the first probe (TCP_KEEPIDLE, Linux only)",
+        raw(requires = r#""keepalive""#)
+    )]
+    idle: Option<humantime::Duration>,
+    #[structopt(
+        long = "keepalive-interval",
+        help = "time between unanswered probes (TCP_KEEPINTVL, Linux only)",
+        raw(requires = r#""keepalive""#)
+    )]
+    interval: Option<humantime::Duration>,
+    #[structopt(
+        long = "keepalive-count",
+        help = "unanswered probes before the connection is dropped (TCP_KEEPCNT, Linux only)",
+        raw(requires = r#""keepalive""#)
+    )]
+    count: Option<u32>,
+}
+
+/// Compression applied to the client's request stream.
+///
+/// The server's response is never compressed.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Compress {
+    None,
+    Gzip,
+}
+
+/// Byte order of the request numbers and the response.
+///
+/// Peers with mismatched byte orders don't agree on parity: the server reads the
+/// odd number's low byte as the most significant one and keeps waiting for an
+/// odd number.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Endian {
+    Big,
+    Little,
+}
+
+/// I/O implementation of the server and the client.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Backend {
+    /// blocking std sockets on OS threads
+    Std,
+    /// tokio sockets, each connection's I/O in tasks
+    Tokio,
+}
+
+/// Socket type carrying the protocol.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum Transport {
+    /// TCP over IPv4 or IPv6
+    Tcp,
+    /// Unix domain stream sockets, as a baseline without FIN or RST
+    Unix,
+}
+
+/// the tokio runtime shared by everything using `--backend tokio`
+#[cfg(feature = "tokio")]
+fn tokio_runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("cannot start tokio runtime")
+    })
+}
+
+/// Runs an in-process server on an ephemeral loopback port and a client
+/// against it, once per value of the swept parameter.
+#[derive(StructOpt)]
+struct Bench {
+    #[structopt(help = "use `modes` subcommand to list modes")]
+    teardown_mode: TeardownMode,
+    #[structopt(help = "parameter to sweep (`sleep` or `send-count`)")]
+    param: BenchParam,
+    #[structopt(
+        help = "comma-separated values of the swept parameter",
+        raw(use_delimiter = "true")
+    )]
+    values: Vec<String>,
+    #[structopt(
+        long = "times",
+        help = "client runs per parameter value",
+        default_value = "1"
+    )]
+    times: usize,
+}
+
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum BenchParam {
+    Sleep,
+    SendCount,
+}
+
+/// Runs the client against an in-process loopback server for every teardown
+/// mode, crossed with linger settings, and compares the result distributions.
+#[derive(StructOpt)]
+struct Experiment {
+    #[structopt(
+        long = "times",
+        help = "client runs per mode and linger setting",
+        default_value = "10"
+    )]
+    times: usize,
+    #[structopt(
+        long = "send-count",
+        help = "maximum number of numbers to send per run",
+        default_value = "8388608"
+    )]
+    send_count: u32,
+    #[structopt(
+        long = "sleep",
+        help = "time to sleep for teardown modes that sleep",
+        default_value = "5ms"
+    )]
+    sleep: humantime::Duration,
+    #[structopt(
+        long = "modes",
+        help = "comma-separated teardown modes to run instead of all of them",
+        raw(use_delimiter = "true")
+    )]
+    modes: Vec<TeardownMode>,
+    #[structopt(
+        long = "linger",
+        help = "comma-separated server linger settings, `off` or a duration, e.g. `off,0s,1s`",
+        default_value = "off",
+        raw(use_delimiter = "true")
+    )]
+    linger: Vec<LingerSetting>,
+}
+
+/// `SO_LINGER` of the experiment's server, `off` or a duration.
+#[derive(Clone, Copy)]
+struct LingerSetting(Option<humantime::Duration>);
+
+impl std::str::FromStr for LingerSetting {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(LingerSetting(None)),
+            s => Ok(LingerSetting(Some(
+                s.parse::<humantime::Duration>().with_context(|_| {
+                    format!("invalid linger {:?}, expected `off` or a duration", s)
+                })?,
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for LingerSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Some(linger) => write!(f, "{}", linger),
+            None => f.write_str("off"),
+        }
+    }
+}
+
+/// Applies teardown primitives read from stdin to a single live connection.
+#[derive(StructOpt)]
+struct Interactive {
+    #[structopt(help = "IP:port to connect to, or to listen on with --accept")]
+    addr: String,
+    #[structopt(
+        long = "accept",
+        help = "accept one connection on `addr` instead of connecting to it"
+    )]
+    accept: bool,
+    #[structopt(
+        long = "framing",
+        help = "message framing used by `send` and `recv` (`numbers` or `length-prefixed`)",
+        default_value = "numbers"
+    )]
+    framing: Framing,
+    #[structopt(
+        long = "word-size",
+        help = "bytes per number and length prefix used by `send` and `recv` (`4` or `8`)",
+        default_value = "4"
+    )]
+    word_size: WordSize,
+    #[structopt(
+        long = "endian",
+        help = "byte order used by `send` and `recv` (`big` or `little`)",
+        default_value = "big"
+    )]
+    endian: Endian,
+}
+
+thread_local! {
+    /// label of the connection the current thread works on, see `ConnLogSpan`
+    static LOG_CONN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Prefixes the current thread's log lines with the `client-server` address
+/// pair of a connection until dropped.
+///
+/// Both sides use the same order, so client and server logs can be joined on it.
+struct ConnLogSpan;
+
+impl ConnLogSpan {
+    fn enter(client: io::Result<net::SocketAddr>, server: io::Result<net::SocketAddr>) -> Self {
+        let label = conn_label(client, server);
+        LOG_CONN.with(|c| *c.borrow_mut() = Some(label));
+        ConnLogSpan
+    }
+}
+
+impl Drop for ConnLogSpan {
+    fn drop(&mut self) {
+        LOG_CONN.with(|c| *c.borrow_mut() = None);
+    }
+}
+
+/// `--transport unix` address: a socket path, or on Linux `@name` for a name
+/// in the abstract namespace, which needs no file and is gone with the socket
+fn unix_addr(addr: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(name) = addr.strip_prefix('@') {
+            use std::os::linux::net::SocketAddrExt;
+            return std::os::unix::net::SocketAddr::from_abstract_name(name);
+        }
+    }
+    std::os::unix::net::SocketAddr::from_pathname(addr)
+}
+
+/// `client-server` address pair identifying a connection on both sides
+fn conn_label(client: io::Result<net::SocketAddr>, server: io::Result<net::SocketAddr>) -> String {
+    let fmt = |addr: io::Result<net::SocketAddr>| {
+        addr.map(|a| a.to_string())
+            .unwrap_or_else(|_| "?".to_string())
+    };
+    format!("{}-{}", fmt(client), fmt(server))
+}
+
+/// log the socket options of `conn` as read back from the kernel
+fn dump_sockopts(conn: &TcpStream) {
+    let sock = socket2::SockRef::from(conn);
+    log::info!("sockopt linger: {:?}", sock.linger());
+    log::info!("sockopt nodelay: {:?}", sock.tcp_nodelay());
+    log::info!("sockopt recv buffer size: {:?}", sock.recv_buffer_size());
+    log::info!("sockopt send buffer size: {:?}", sock.send_buffer_size());
+    log::info!("sockopt keepalive: {:?}", sock.keepalive());
+    log::info!("sockopt reuseaddr: {:?}", sock.reuse_address());
+    #[cfg(unix)]
+    log::info!("sockopt reuseport: {:?}", sock.reuse_port());
+    #[cfg(target_os = "linux")]
+    log::info!("sockopt user timeout: {:?}", sock.tcp_user_timeout());
+}
+
+/// log the kernel's view of `conn` from `/proc/net/tcp{,6}`, matched by its four-tuple
+fn log_proc_tcp_stats(conn: &TcpStream) {
+    #[cfg(target_os = "linux")]
+    {
+        let (local, peer) = match (conn.local_addr(), conn.peer_addr()) {
+            (Ok(local), Ok(peer)) => (local, peer),
+            (local, peer) => {
+                log::warn!("cannot get connection addresses: {:?} {:?}", local, peer);
+                return;
+            }
+        };
+        let table = if local.is_ipv4() {
+            "/proc/net/tcp"
+        } else {
+            "/proc/net/tcp6"
+        };
+        match proc_tcp_entry(table, local, peer) {
+            Ok(Some(entry)) => log::info!(
+                "{}: state {}, send queue {} bytes, receive queue {} bytes",
+                table,
+                entry.state,
+                entry.tx_queue,
+                entry.rx_queue
+            ),
+            Ok(None) => log::warn!("{}: no entry for {} -> {}", table, local, peer),
+            Err(e) => log::warn!("cannot read {}: {:?}", table, e),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = conn;
+        log::warn!("--proc-stats is Linux only, continuing without");
+    }
+}
+
+/// the kernel's name of a TCP state, as numbered in `/proc/net/tcp` and `TCP_INFO`
+#[cfg(target_os = "linux")]
+fn tcp_state_name(state: u8) -> &'static str {
+    match state {
+        0x01 => "ESTABLISHED",
+        0x02 => "SYN_SENT",
+        0x03 => "SYN_RECV",
+        0x04 => "FIN_WAIT1",
+        0x05 => "FIN_WAIT2",
+        0x06 => "TIME_WAIT",
+        0x07 => "CLOSE",
+        0x08 => "CLOSE_WAIT",
+        0x09 => "LAST_ACK",
+        0x0A => "LISTEN",
+        0x0B => "CLOSING",
+        _ => "UNKNOWN",
+    }
+}
+
+/// log the connection's `TCP_INFO` and socket queue sizes, labeled `when`
+///
+/// The socket must still be open, so this can't show the state after the close.
+fn log_tcp_info(conn: &TcpStream, when: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = conn.as_raw_fd();
+        let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                &mut info as *mut libc::tcp_info as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            log::warn!(
+                "cannot get TCP_INFO {}: {:?}",
+                when,
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        let queues = socket_queues(conn);
+        log::info!(
+            "TCP_INFO {}: state {}, retransmits {} (total {}), rtt {}us (var {}us), unacked {} segments, lost {}, send queue {:?} bytes, receive queue {:?} bytes",
+            when,
+            tcp_state_name(info.tcpi_state),
+            info.tcpi_retransmits,
+            info.tcpi_total_retrans,
+            info.tcpi_rtt,
+            info.tcpi_rttvar,
+            info.tcpi_unacked,
+            info.tcpi_lost,
+            queues.as_ref().map(|q| q.0),
+            queues.as_ref().map(|q| q.1),
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (conn, when);
+        log::warn!("--tcp-info is Linux only, continuing without");
+    }
+}
+
+/// bytes in the connection's send queue, i.e. not yet acknowledged by the
+/// peer, and in its receive queue, i.e. received but not yet read
+///
+/// `SIOCOUTQ` and `SIOCINQ`, Linux only.
+fn socket_queues(conn: &TcpStream) -> io::Result<(u64, u64)> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let queue = |request| {
+            let mut bytes: libc::c_int = 0;
+            if unsafe { libc::ioctl(conn.as_raw_fd(), request, &mut bytes) } == 0 {
+                Ok(bytes as u64)
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        };
+        // SIOCOUTQ and SIOCINQ are the TTY ioctls' numbers
+        Ok((queue(libc::TIOCOUTQ)?, queue(libc::FIONREAD)?))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = conn;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "socket queue sizes are Linux only",
+        ))
+    }
+}
+
+/// A connection's row in `/proc/net/tcp{,6}`.
+#[cfg(target_os = "linux")]
+struct ProcTcpEntry {
+    state: &'static str,
+    /// bytes sent but not yet acknowledged, plus unsent ones
+    tx_queue: u64,
+    /// bytes received but not yet read by the application
+    rx_queue: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn proc_tcp_entry(
+    table: &str,
+    local: net::SocketAddr,
+    peer: net::SocketAddr,
+) -> Result<Option<ProcTcpEntry>, failure::Error> {
+    use std::convert::TryFrom;
+
+    // Addresses are the hex of the network-order bytes read as native-endian
+    // 32-bit words, ports are plain hex, e.g. `0100007F:2328`.
+    let parse_addr = |s: &str| -> Option<net::SocketAddr> {
+        let (ip, port) = s.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+        let mut bytes = Vec::with_capacity(16);
+        for i in (0..ip.len()).step_by(8) {
+            let word = u32::from_str_radix(ip.get(i..i + 8)?, 16).ok()?;
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+        let ip = match bytes.len() {
+            4 => net::IpAddr::from(<[u8; 4]>::try_from(&bytes[..]).ok()?),
+            16 => net::IpAddr::from(<[u8; 16]>::try_from(&bytes[..]).ok()?),
+            _ => return None,
+        };
+        Some(net::SocketAddr::new(ip, port))
+    };
+    let contents = std::fs::read_to_string(table)?;
+    for line in contents.lines().skip(1) {
+        // sl local_address rem_address st tx_queue:rx_queue ...
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        if parse_addr(fields[1]) != Some(local) || parse_addr(fields[2]) != Some(peer) {
+            continue;
+        }
+        let state = u8::from_str_radix(fields[3], 16).map_or("UNKNOWN", tcp_state_name);
+        let (tx_queue, rx_queue) = fields[4]
+            .split_once(':')
+            .ok_or_else(|| failure::format_err!("invalid queue sizes {:?}", fields[4]))?;
+        return Ok(Some(ProcTcpEntry {
+            state,
+            tx_queue: u64::from_str_radix(tx_queue, 16)?,
+            rx_queue: u64::from_str_radix(rx_queue, 16)?,
+        }));
+    }
+    Ok(None)
+}
+
+/// log to stderr like the binary does, prefixing each line with the
+/// connection it is about, unless `RUST_LOG` overrides `level`
+pub fn init_logger(level: log::LevelFilter) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level.to_string()))
+        .format(|buf, record| {
+            let conn = LOG_CONN.with(|c| {
+                c.borrow()
+                    .as_ref()
+                    .map(|label| format!("[{}] ", label))
+                    .unwrap_or_default()
+            });
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}{}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.module_path().unwrap_or_default(),
+                conn,
+                record.args()
+            )
+        })
+        .init();
+}
+
+impl Opt {
+    /// run the subcommand
+    pub fn run(&self) -> Result<(), failure::Error> {
+        self.app.run()
+    }
+
+    /// log level derived from `-q` / `-v`, starting at `debug`
+    pub fn log_level(&self) -> log::LevelFilter {
+        let levels = [
+            log::LevelFilter::Off,
+            log::LevelFilter::Error,
+            log::LevelFilter::Warn,
+            log::LevelFilter::Info,
+            log::LevelFilter::Debug,
+            log::LevelFilter::Trace,
+        ];
+        let default = 4; // debug
+        let idx = (default + self.verbose as isize - self.quiet as isize)
+            .max(0)
+            .min(levels.len() as isize - 1);
+        levels[idx as usize]
+    }
+}
+
+impl App {
+    fn run(&self) -> Result<(), failure::Error> {
+        match self {
+            App::Server(s) => s.run(),
+            App::Client(c) => c.run(),
+            App::Modes(m) => m.run(),
+            App::Bench(b) => b.run(),
+            App::Interactive(i) => i.run(),
+            App::Experiment(e) => e.run(),
+            App::Proxy(p) => p.run(),
+        }
+    }
+}
+
+impl Framing {
+    /// write a message carrying `num`
+    fn write_message<W: Write>(
+        self,
+        w: &mut W,
+        endian: Endian,
+        word_size: WordSize,
+        num: u32,
+    ) -> io::Result<()> {
+        w.write_all(&word_size.encode(endian, num))?;
+        if let Framing::LengthPrefixed = self {
+            io::copy(&mut io::repeat(0).take(num.into()), w)?;
+        }
+        Ok(())
+    }
+
+    /// read the next message and return the number it carries
+    fn read_message<R: Read>(
+        self,
+        r: &mut R,
+        endian: Endian,
+        word_size: WordSize,
+    ) -> io::Result<u32> {
+        let mut buf = [0_u8; 8];
+        let buf = &mut buf[..word_size.bytes()];
+        r.read_exact(buf)?;
+        let num = word_size.decode(endian, buf)?;
+        if let Framing::LengthPrefixed = self {
+            // the payload is irrelevant, only its length matters
+            let skipped = io::copy(&mut r.take(num.into()), &mut io::sink())?;
+            if skipped < num.into() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed within message payload",
+                ));
+            }
+        }
+        Ok(num)
+    }
+}
+
+impl WordSize {
+    fn bytes(self) -> usize {
+        match self {
+            WordSize::U32 => 4,
+            WordSize::U64 => 8,
+        }
+    }
+
+    fn encode(self, endian: Endian, num: u32) -> Vec<u8> {
+        let mut buf = vec![0_u8; self.bytes()];
+        match (self, endian) {
+            (WordSize::U32, _) => endian.write_u32(&mut buf, num),
+            (WordSize::U64, Endian::Big) => BigEndian::write_u64(&mut buf, num.into()),
+            (WordSize::U64, Endian::Little) => LittleEndian::write_u64(&mut buf, num.into()),
+        }
+        buf
+    }
+
+    fn decode(self, endian: Endian, buf: &[u8]) -> io::Result<u32> {
+        let num = match (self, endian) {
+            (WordSize::U32, _) => return Ok(endian.read_u32(buf)),
+            (WordSize::U64, Endian::Big) => BigEndian::read_u64(buf),
+            (WordSize::U64, Endian::Little) => LittleEndian::read_u64(buf),
+        };
+        std::convert::TryFrom::try_from(num).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("number {} does not fit 32 bits", num),
+            )
+        })
+    }
+}
+
+impl Keepalive {
+    /// set the options on a socket before it listens or connects, accepted
+    /// connections inherit them from the listening socket
+    fn apply(&self, socket: &socket2::Socket) -> io::Result<()> {
+        if !self.keepalive {
+            return Ok(());
+        }
+        socket.set_keepalive(true)?;
+        #[cfg(target_os = "linux")]
+        {
+            let secs = |d: humantime::Duration| {
+                std::time::Duration::from_secs(d.as_nanos().div_ceil(1_000_000_000).max(1) as u64)
+            };
+            let mut params = socket2::TcpKeepalive::new();
+            if let Some(idle) = self.idle {
+                params = params.with_time(secs(idle));
+            }
+            if let Some(interval) = self.interval {
+                params = params.with_interval(secs(interval));
+            }
+            if let Some(count) = self.count {
+                params = params.with_retries(count);
+            }
+            socket.set_tcp_keepalive(&params)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            if self.idle.is_some() || self.interval.is_some() || self.count.is_some() {
+                log::warn!("keepalive tuning is Linux only, using the system defaults");
+            }
+        }
+        log::info!(
+            "enabled keepalive, idle {:?}, interval {:?}, count {:?}",
+            self.idle.map(|d| d.to_string()),
+            self.interval.map(|d| d.to_string()),
+            self.count
+        );
+        Ok(())
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "enabled": self.keepalive,
+            "idle": self.idle.map(|d| d.to_string()),
+            "interval": self.interval.map(|d| d.to_string()),
+            "count": self.count,
+        })
+    }
+}
+
+/// set an integer socket option
+#[cfg(unix)]
+fn setsockopt_int(
+    socket: &impl std::os::unix::io::AsRawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    val: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &val as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// set TCP_USER_TIMEOUT on a socket before it listens or connects, accepted
+/// connections inherit it from the listening socket
+fn set_user_timeout(socket: &socket2::Socket, timeout: std::time::Duration) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // 0 would mean the system default, round up to at least 1ms
+        let millis = timeout.as_nanos().div_ceil(1_000_000).max(1);
+        let millis = millis.min(libc::c_int::MAX as u128) as u64;
+        socket.set_tcp_user_timeout(Some(std::time::Duration::from_millis(millis)))?;
+        log::info!("set TCP_USER_TIMEOUT to {}ms", millis);
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, timeout);
+        log::warn!("TCP_USER_TIMEOUT is Linux only, continuing without");
+    }
+    Ok(())
+}
+
+/// set SO_SNDBUF and SO_RCVBUF on a socket before it listens or connects, so
+/// the receive buffer also bounds the window scale offered in the handshake
+///
+/// Accepted connections inherit them from the listening socket. Linux doubles
+/// the requested sizes for bookkeeping overhead, `--dump-sockopts` shows the result.
+fn set_buffer_sizes(
+    socket: &socket2::Socket,
+    sndbuf: Option<u32>,
+    rcvbuf: Option<u32>,
+) -> io::Result<()> {
+    if let Some(bytes) = sndbuf {
+        socket.set_send_buffer_size(bytes as usize)?;
+        log::info!("set SO_SNDBUF to {} bytes", bytes);
+    }
+    if let Some(bytes) = rcvbuf {
+        socket.set_recv_buffer_size(bytes as usize)?;
+        log::info!("set SO_RCVBUF to {} bytes", bytes);
+    }
+    Ok(())
+}
+
+/// a blocking TCP socket of `addr`'s family, to set options on before it
+/// listens or connects
+fn tcp_socket(addr: net::SocketAddr) -> io::Result<socket2::Socket> {
+    socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )
+}
+
+impl Endian {
+    fn read_u32(self, buf: &[u8]) -> u32 {
+        match self {
+            Endian::Big => BigEndian::read_u32(buf),
+            Endian::Little => LittleEndian::read_u32(buf),
+        }
+    }
+
+    fn write_u32(self, buf: &mut [u8], num: u32) {
+        match self {
+            Endian::Big => BigEndian::write_u32(buf, num),
+            Endian::Little => LittleEndian::write_u32(buf, num),
+        }
+    }
+}
+
+/// Retries reads & writes that fail with `WouldBlock` until they succeed,
+/// making a nonblocking socket usable with the blocking I/O code paths.
+///
+/// Transparent for blocking sockets without timeouts.
+struct SpinOnWouldBlock<T>(T);
+
+impl<T: Read> Read for SpinOnWouldBlock<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T: Write> Write for SpinOnWouldBlock<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.0.write(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                res => return res,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match self.0.flush() {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                res => return res,
+            }
+        }
+    }
+}
+
+/// The socket operations of a client-side teardown, on TCP and Unix domain sockets.
+trait StreamSocket {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> io::Result<()>;
+    fn shutdown(&self, how: net::Shutdown) -> io::Result<()>;
+    /// `SO_LINGER`, which Unix domain sockets lack
+    fn set_linger(&self, linger: Option<std::time::Duration>) -> io::Result<()>;
+}
+
+impl StreamSocket for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self, how: net::Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_linger(&self, linger: Option<std::time::Duration>) -> io::Result<()> {
+        socket2::SockRef::from(self).set_linger(linger)
+    }
+}
+
+impl StreamSocket for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self, how: net::Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+
+    fn set_linger(&self, _linger: Option<std::time::Duration>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets have no linger option",
+        ))
+    }
+}
+
+/// Closes `conn`, through io_uring's `IORING_OP_CLOSE` if `io_uring`.
+///
+/// Only the last handle of a `try_clone`d connection actually closes the socket.
+fn close_conn(conn: TcpStream, io_uring: bool) {
+    #[cfg(feature = "io-uring")]
+    {
+        if io_uring {
+            if let Err(e) = uring::close(conn) {
+                log::warn!("io_uring close failed: {:?}", e);
+            }
+            return;
+        }
+    }
+    let _ = io_uring;
+    drop(conn);
+}
+
+impl Compress {
+    /// buffered reader of the decompressed request stream on `conn`
+    fn reader<'a, R: Read + 'a>(self, conn: R) -> Box<dyn Read + 'a> {
+        match self {
+            // use buffered I/O to avoid a syscall for every number
+            Compress::None => Box::new(BufReader::new(conn)),
+            // buffers internally
+            Compress::Gzip => Box::new(flate2::read::GzDecoder::new(conn)),
+        }
+    }
+
+    /// writer compressing into `w`
+    fn writer<'a, W: Write + 'a>(self, w: W) -> Box<dyn Write + 'a> {
+        match self {
+            Compress::None => Box::new(w),
+            Compress::Gzip => Box::new(flate2::write::GzEncoder::new(
+                w,
+                flate2::Compression::fast(),
+            )),
+        }
+    }
+}
+
+impl Interactive {
+    const HELP: &'static str = "commands: send <u32>, recv, shutdown read|write|both, drain, linger <duration>|off, nodelay on|off, close, help";
+
+    fn run(&self) -> Result<(), failure::Error> {
+        let mut conn = if self.accept {
+            let listener = net::TcpListener::bind(&self.addr).context("bind")?;
+            println!("waiting for a connection on {}", listener.local_addr()?);
+            listener.accept().context("accept")?.0
+        } else {
+            TcpStream::connect(&self.addr).context("connect")?
+        };
+        println!("connected {} -> {}", conn.local_addr()?, conn.peer_addr()?);
+        println!("{}", Self::HELP);
+
+        for line in io::stdin().lock().lines() {
+            let line = line.context("read command")?;
+            let words: Vec<_> = line.split_whitespace().collect();
+            let res = match words.as_slice() {
+                [] => continue,
+                ["close"] => break,
+                ["help"] => Ok(Self::HELP.to_string()),
+                cmd => self.apply(&mut conn, cmd),
+            };
+            match res {
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("error: {}", e),
+            }
+        }
+        drop(conn);
+        println!("closed");
+        Ok(())
+    }
+
+    /// apply a single command to `conn`, returning what to print
+    fn apply(&self, conn: &mut TcpStream, cmd: &[&str]) -> Result<String, failure::Error> {
+        match cmd {
+            ["send", num] => {
+                let num = num
+                    .parse::<u32>()
+                    .with_context(|_| format!("invalid number {:?}", num))?;
+                self.framing
+                    .write_message(conn, self.endian, self.word_size, num)
+                    .context("write")?;
+                Ok(format!("sent {}", num))
+            }
+            ["recv"] => {
+                let num = self
+                    .framing
+                    .read_message(conn, self.endian, self.word_size)
+                    .context("read")?;
+                Ok(format!("received {}", num))
+            }
+            ["shutdown", how] => {
+                let how = match *how {
+                    "read" => net::Shutdown::Read,
+                    "write" => net::Shutdown::Write,
+                    "both" => net::Shutdown::Both,
+                    _ => failure::bail!("expected read, write or both, got {:?}", how),
+                };
+                conn.shutdown(how).context("shutdown")?;
+                Ok(format!("shut down {:?}", how))
+            }
+            ["drain"] => {
+                let drained = Server::drain(conn)?;
+                Ok(format!("drained {} bytes until EOF", drained))
+            }
+            ["linger", "off"] => {
+                socket2::SockRef::from(&*conn)
+                    .set_linger(None)
+                    .context("set linger")?;
+                Ok("linger off".to_string())
+            }
+            ["linger", duration] => {
+                let duration = humantime::parse_duration(duration)
+                    .with_context(|_| format!("invalid duration {:?}", duration))?;
+                socket2::SockRef::from(&*conn)
+                    .set_linger(Some(duration))
+                    .context("set linger")?;
+                Ok(format!("linger {:?}", duration))
+            }
+            ["nodelay", on @ ("on" | "off")] => {
+                conn.set_nodelay(*on == "on").context("set nodelay")?;
+                Ok(format!("nodelay {}", on))
+            }
+            _ => failure::bail!("unknown command {:?}, {}", cmd.join(" "), Self::HELP),
+        }
+    }
+}
+
+/// Outcome of `loopback_run`.
+struct LoopbackRun {
+    /// the client's results
+    runs: HashMap<SingleRunResult, usize>,
+    /// the server's stats, or why it stopped early
+    server: Result<ServerStats, failure::Error>,
+}
+
+/// run the client `times` against an in-process server tearing down with `mode`
+/// on an ephemeral loopback port
+fn loopback_run(
+    mode: TeardownMode,
+    sleep: humantime::Duration,
+    linger: Option<humantime::Duration>,
+    send_count: u32,
+    times: usize,
+) -> Result<LoopbackRun, failure::Error> {
+    let listener = net::TcpListener::bind("127.0.0.1:0").context("bind")?;
+    let addr = listener.local_addr().context("listener address")?;
+    log::info!("loopback server listening on {:?}", addr);
+    let mut server = Server::defaults(addr, mode);
+    server.sleep = sleep;
+    server.linger = linger;
+    let server_thread = std::thread::spawn(move || {
+        server.serve(listener, Some(times), &ReportLog::open(None)?, None)
+    });
+
+    let mut client = Client::defaults(addr);
+    client.times = times;
+    client.send_count = send_count;
+    let runs = client.run_batch(None, &RunLog::open(None)?).runs;
+    let server = server_thread.join().expect("server thread panicked");
+    Ok(LoopbackRun { runs, server })
+}
+
+impl Bench {
+    fn run(&self) -> Result<(), failure::Error> {
+        let mut sleep: humantime::Duration = std::time::Duration::from_millis(5).into();
+        let mut send_count = 1 << 23;
+
+        println!("{},result,count", self.param);
+        for value in &self.values {
+            match self.param {
+                BenchParam::Sleep => {
+                    sleep = value
+                        .parse::<humantime::Duration>()
+                        .with_context(|_| format!("invalid sleep value {:?}", value))?
+                }
+                BenchParam::SendCount => {
+                    send_count = value
+                        .parse::<u32>()
+                        .with_context(|_| format!("invalid send-count value {:?}", value))?
+                }
+            }
+
+            let LoopbackRun { runs, server } =
+                loopback_run(self.teardown_mode, sleep, None, send_count, self.times)?;
+            server?;
+            let mut stats: Vec<_> = runs
+                .into_iter()
+                .map(|(res, count)| (format!("{:?}", res), count))
+                .collect();
+            stats.sort();
+
+            for (res, count) in stats {
+                println!("{},\"{}\",{}", value, res, count);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Experiment {
+    fn run(&self) -> Result<(), failure::Error> {
+        let modes: Vec<_> = if self.modes.is_empty() {
+            TeardownMode::all().collect()
+        } else {
+            self.modes.clone()
+        };
+        let mut rows = Vec::new();
+        for &mode in &modes {
+            for &linger in &self.linger {
+                log::info!("running {} with linger {}", mode, linger);
+                let LoopbackRun { runs, server } =
+                    loopback_run(mode, self.sleep, linger.0, self.send_count, self.times)?;
+                if let Err(e) = &server {
+                    log::error!("server failed for {} with linger {}: {:?}", mode, linger, e);
+                }
+                let runs: HashMap<_, _> = runs
+                    .into_iter()
+                    .map(|(res, count)| (format!("{:?}", res), count))
+                    .collect();
+                rows.push((mode, linger, runs, server.is_ok()));
+            }
+        }
+
+        let categories: std::collections::BTreeSet<_> = rows
+            .iter()
+            .flat_map(|(_, _, runs, _)| runs.keys())
+            .collect();
+        let mode_width = modes.iter().map(|m| m.to_string().len()).max().unwrap_or(0);
+        let mut header = format!("{:<w$}  {:<8}", "mode", "linger", w = mode_width);
+        for category in &categories {
+            header += &format!("  {}", category);
+        }
+        println!("{}  server", header);
+        for (mode, linger, runs, server_ok) in &rows {
+            let mut line = format!(
+                "{:<w$}  {:<8}",
+                mode.to_string(),
+                linger.to_string(),
+                w = mode_width
+            );
+            for category in &categories {
+                let count = runs.get(*category).copied().unwrap_or(0);
+                line += &format!("  {:>w$}", count, w = category.len());
+            }
+            println!("{}  {}", line, if *server_ok { "ok" } else { "error" });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn teardown_mode_codes_round_trip() {
+        let mut codes = std::collections::BTreeSet::new();
+        for mode in TeardownMode::all() {
+            assert!(
+                codes.insert(mode.code()),
+                "{} reuses code {}",
+                mode,
+                mode.code()
+            );
+            assert_eq!(
+                TeardownMode::from_code(mode.code()).map(|m| m.to_string()),
+                Some(mode.to_string())
+            );
+        }
+        assert!(TeardownMode::from_code(u32::MAX).is_none());
+    }
+
+    #[test]
+    fn sockopt_by_name_and_number() {
+        let opt: SockOpt = "tcp_nodelay=1".parse().unwrap();
+        assert_eq!(
+            (opt.name.as_str(), opt.level, opt.optname, opt.value),
+            ("TCP_NODELAY", libc::IPPROTO_TCP, libc::TCP_NODELAY, 1)
+        );
+        assert_eq!(opt.to_string(), "TCP_NODELAY=1");
+
+        let opt: SockOpt = "6:0x1=0x10".parse().unwrap();
+        assert_eq!((opt.level, opt.optname, opt.value), (6, 1, 16));
+
+        let opt: SockOpt = "IP_TTL=-1".parse().unwrap();
+        assert_eq!(opt.value, -1);
+    }
+
+    #[test]
+    fn sockopt_rejects_malformed_and_out_of_range() {
+        for bad in [
+            "TCP_NODELAY",
+            "TCP_NODELAY=",
+            "TCP_NODELAY=yes",
+            "SO_NOSUCHOPT=1",
+            "6:x=1",
+            ":1=1",
+            "0xzz:1=1",
+            "SO_MARK=4294967296",
+            "SO_MARK=0x100000000",
+        ] {
+            assert!(bad.parse::<SockOpt>().is_err(), "{:?} parsed", bad);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! A forwarding proxy that misbehaves like a broken middlebox.
+
+use std::io::prelude::*;
+use std::net::{self, TcpStream};
+use std::sync::atomic::{self, AtomicBool};
+
+use failure::ResultExt;
+use structopt::StructOpt;
+
+use crate::{ConnLogSpan, StreamSocket, LOG_CONN};
+
+/// Misbehavior of the `proxy` subcommand, applied to the directions selected by
+/// `--fault-direction`.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum ProxyFault {
+    /// forward bytes, FINs and RSTs faithfully
+    None,
+    /// don't forward a FIN: the receiver only sees the end of the stream once
+    /// the other direction is done too and the proxy closes both connections
+    DropFin,
+    /// answer a FIN by resetting both connections
+    InjectRst,
+    /// forward FINs and RSTs only after `--fault-delay`
+    DelayTeardown,
+    /// discard all bytes and the FIN instead of forwarding them
+    Blackhole,
+}
+
+/// Which directions of a proxied connection `--fault` applies to.
+#[derive(Clone, Copy, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum ProxyDirection {
+    Both,
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Forwards connections to a server, optionally misbehaving like a broken
+/// middlebox.
+#[derive(StructOpt)]
+pub struct Proxy {
+    #[structopt(help = "IP:port to listen on for clients")]
+    listen: String,
+    #[structopt(help = "IP:port of the server to forward connections to")]
+    upstream: String,
+    #[structopt(
+        long = "fault",
+        help = "`none`, `drop-fin`, `inject-rst`, `delay-teardown` or `blackhole`",
+        default_value = "none"
+    )]
+    fault: ProxyFault,
+    #[structopt(
+        long = "fault-direction",
+        help = "`both`, `client-to-server` or `server-to-client`",
+        default_value = "both"
+    )]
+    fault_direction: ProxyDirection,
+    #[structopt(
+        long = "fault-delay",
+        help = "how long `delay-teardown` holds back FINs and RSTs",
+        default_value = "100ms"
+    )]
+    fault_delay: humantime::Duration,
+}
+
+/// The two connections of a proxied connection.
+struct ProxyConn {
+    client: TcpStream,
+    server: TcpStream,
+    /// set once the proxy reset both connections
+    aborted: AtomicBool,
+}
+
+impl Proxy {
+    pub fn run(&self) -> Result<(), failure::Error> {
+        let listener = net::TcpListener::bind(&self.listen).context("bind")?;
+        log::info!(
+            "proxying {} -> {} with fault {} ({})",
+            listener.local_addr()?,
+            self.upstream,
+            self.fault,
+            self.fault_direction
+        );
+        std::thread::scope(|scope| {
+            for conn in listener.incoming() {
+                let conn = conn.context("accept")?;
+                scope.spawn(move || {
+                    let _span = ConnLogSpan::enter(conn.peer_addr(), conn.local_addr());
+                    if let Err(e) = self.proxy_conn(conn) {
+                        log::warn!("proxying failed: {:?}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn proxy_conn(&self, client: TcpStream) -> Result<(), failure::Error> {
+        let server = TcpStream::connect(&self.upstream).context("connect upstream")?;
+        log::info!(
+            "forwarding to {} via {}",
+            server.peer_addr()?,
+            server.local_addr()?
+        );
+        let conn = ProxyConn {
+            client,
+            server,
+            aborted: AtomicBool::new(false),
+        };
+        let label = LOG_CONN.with(|c| c.borrow().clone());
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                LOG_CONN.with(|c| *c.borrow_mut() = label);
+                self.pump(&conn, ProxyDirection::ClientToServer)
+            });
+            self.pump(&conn, ProxyDirection::ServerToClient)
+        });
+        // dropping both connections sends the FINs held back by `drop-fin`, or
+        // the RSTs of an abort
+        Ok(())
+    }
+
+    /// forward one direction of `conn` until its sender is done
+    fn pump(&self, conn: &ProxyConn, dir: ProxyDirection) {
+        let (mut from, mut to) = match dir {
+            ProxyDirection::ServerToClient => (&conn.server, &conn.client),
+            _ => (&conn.client, &conn.server),
+        };
+        let faulty = self.fault_direction == ProxyDirection::Both || self.fault_direction == dir;
+        let fault = if faulty { self.fault } else { ProxyFault::None };
+        let mut buf = vec![0u8; 64 << 10];
+        let mut received = 0u64;
+        loop {
+            let n = match from.read(&mut buf) {
+                Ok(0) if conn.aborted.load(atomic::Ordering::SeqCst) => return,
+                Ok(0) => break,
+                Err(e) => {
+                    log::info!("{}: read failed after {} bytes: {}", dir, received, e);
+                    if let ProxyFault::DelayTeardown = fault {
+                        std::thread::sleep(*self.fault_delay);
+                    }
+                    self.abort(conn, dir);
+                    return;
+                }
+                Ok(n) => n,
+            };
+            received += n as u64;
+            if let ProxyFault::Blackhole = fault {
+                continue;
+            }
+            if let Err(e) = to.write_all(&buf[..n]) {
+                log::info!("{}: write failed after {} bytes: {}", dir, received, e);
+                self.abort(conn, dir);
+                return;
+            }
+        }
+        log::info!("{}: FIN after {} bytes", dir, received);
+        match fault {
+            ProxyFault::None => {}
+            ProxyFault::DropFin | ProxyFault::Blackhole => {
+                log::info!("{}: dropping FIN", dir);
+                return;
+            }
+            ProxyFault::InjectRst => {
+                self.abort(conn, dir);
+                return;
+            }
+            ProxyFault::DelayTeardown => {
+                log::info!("{}: delaying FIN by {}", dir, self.fault_delay);
+                std::thread::sleep(*self.fault_delay);
+            }
+        }
+        if let Err(e) = to.shutdown(net::Shutdown::Write) {
+            log::info!("{}: forwarding FIN failed: {}", dir, e);
+        }
+    }
+
+    /// reset both connections of `conn` once they are dropped, waking up the
+    /// other direction's pump
+    fn abort(&self, conn: &ProxyConn, dir: ProxyDirection) {
+        log::info!("{}: resetting both connections", dir);
+        conn.aborted.store(true, atomic::Ordering::SeqCst);
+        for stream in &[&conn.client, &conn.server] {
+            // a zero linger makes the close send an RST, shutting down the read
+            // side unblocks the other pump without sending anything
+            let _ = StreamSocket::set_linger(*stream, Some(std::time::Duration::from_secs(0)));
+            let _ = stream.shutdown(net::Shutdown::Read);
+        }
+    }
+}
@@ -0,0 +1,2671 @@
+//! The server: accepts connections, runs the request protocol or workload
+//! on each, and tears it down with the configured mode.
+
+use std::io::{self, prelude::*};
+use std::net::{self, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{
+    atomic::{self, AtomicBool},
+    Arc, Mutex,
+};
+
+use failure::ResultExt;
+use rand::{RngExt, SeedableRng};
+use structopt::StructOpt;
+
+use crate::stats::{ServerStats, ServerSummary};
+#[cfg(feature = "tls")]
+use crate::tls;
+#[cfg(feature = "tokio")]
+use crate::tokio_runtime;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+use crate::{
+    capture, close_conn, conn_label, dump_sockopts, log_proc_tcp_stats, log_tcp_info,
+    set_buffer_sizes, set_user_timeout, socket_queues, tcp_socket, unix_addr, Backend, Compress,
+    ConnLogSpan, CpuList, Endian, Framing, Keepalive, ShutdownHalf, SockOpt, SpinOnWouldBlock,
+    TeardownMode, Transport, WordSize, Workload,
+};
+
+#[derive(StructOpt)]
+pub struct Server {
+    #[structopt(help = "bind listening to socket to IP:port, `[IPv6]:port` for IPv6")]
+    listen: String,
+    #[structopt(
+        help = "use `modes` subcommand to list modes, or `random` to pick one per connection (uniformly unless --mode-weights, reproducibly with --seed)"
+    )]
+    teardown_mode: ServerMode,
+    #[structopt(
+        long = "sleep",
+        help = "time to sleep for teardown modes that sleep",
+        default_value = "5ms"
+    )]
+    pub sleep: humantime::Duration,
+    #[structopt(
+        long = "drain-limit",
+        help = "draining teardown modes give up after reading this many bytes, leaving the rest queued"
+    )]
+    drain_limit: Option<u64>,
+    #[structopt(
+        long = "drain-timeout",
+        help = "draining teardown modes give up if EOF does not arrive within this time (e.g. `1s`)"
+    )]
+    drain_timeout: Option<humantime::Duration>,
+    #[structopt(
+        long = "sleep-dist",
+        help = "draw the sleep per connection instead, `uniform:MIN:MAX` or `exp:MEAN`"
+    )]
+    sleep_dist: Option<SleepDist>,
+    #[structopt(
+        long = "seed",
+        help = "seed for per-connection random choices, for reproducible experiments"
+    )]
+    seed: Option<u64>,
+    #[structopt(
+        long = "linger",
+        help = "enable lingering for client connections (e.g. `2s`)"
+    )]
+    pub linger: Option<humantime::Duration>,
+    #[structopt(
+        long = "framing",
+        help = "message framing, must match the client (`numbers` or `length-prefixed`)",
+        default_value = "numbers"
+    )]
+    framing: Framing,
+    #[structopt(
+        long = "word-size",
+        help = "bytes per number and length prefix, must match the client (`4` or `8`)",
+        default_value = "4"
+    )]
+    word_size: WordSize,
+    #[structopt(flatten)]
+    keepalive: Keepalive,
+    #[structopt(
+        long = "tcp-user-timeout",
+        help = "drop the connection once sent data stays unacknowledged this long, including queued teardown data (TCP_USER_TIMEOUT, Linux only)"
+    )]
+    tcp_user_timeout: Option<humantime::Duration>,
+    #[structopt(
+        long = "nodelay",
+        help = "disable Nagle's algorithm (TCP_NODELAY), so small writes go out without waiting for ACKs"
+    )]
+    nodelay: bool,
+    #[structopt(
+        long = "sndbuf",
+        help = "send buffer size in bytes (SO_SNDBUF), small buffers fill up after fewer numbers"
+    )]
+    sndbuf: Option<u32>,
+    #[structopt(
+        long = "rcvbuf",
+        help = "receive buffer size in bytes (SO_RCVBUF), small buffers leave unread data at close after fewer numbers"
+    )]
+    rcvbuf: Option<u32>,
+    #[structopt(
+        long = "sockopt",
+        help = "set a raw integer socket option before listening, accepted connections inherit most, e.g. `IP_TOS=0x10`, or `LEVEL:OPTNAME=VALUE` in numbers for options without a name here (repeatable, Unix only)",
+        raw(number_of_values = "1")
+    )]
+    sockopts: Vec<SockOpt>,
+    #[structopt(
+        long = "backlog",
+        help = "length of the listening socket's accept queue",
+        default_value = "128"
+    )]
+    backlog: i32,
+    #[structopt(
+        long = "v6-only",
+        help = "with an IPv6 listen address, accept only IPv6 connections instead of dual-stack"
+    )]
+    v6_only: bool,
+    #[structopt(
+        long = "close-after",
+        help = "number of even numbers to read in close-after-n-even-numbers mode",
+        default_value = "1000"
+    )]
+    close_after: u64,
+    #[structopt(
+        long = "close-after-mode",
+        help = "teardown performed by close-after-n-even-numbers mode",
+        default_value = "close-immediately"
+    )]
+    close_after_mode: TeardownMode,
+    #[structopt(
+        long = "compress",
+        help = "decompress the request stream, must match the client (`none` or `gzip`)",
+        default_value = "none"
+    )]
+    compress: Compress,
+    #[structopt(
+        long = "endian",
+        help = "byte order of the numbers on the wire, must match the peer (`big` or `little`)",
+        default_value = "big"
+    )]
+    endian: Endian,
+    #[structopt(
+        long = "nonblocking",
+        help = "put accepted connections into nonblocking mode, spinning on WouldBlock"
+    )]
+    nonblocking: bool,
+    #[structopt(
+        long = "events-out",
+        help = "write a CSV timeline of socket events per connection to this file",
+        parse(from_os_str)
+    )]
+    events_out: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "flood-bytes",
+        help = "number of bytes written after the response in flood-then-close mode",
+        default_value = "4194304"
+    )]
+    flood_bytes: u64,
+    #[structopt(
+        long = "flood-mode",
+        help = "teardown performed by flood-then-close mode once the flood is written",
+        default_value = "close-immediately"
+    )]
+    flood_mode: TeardownMode,
+    #[structopt(
+        long = "write-timeout",
+        help = "give up writing the flood in flood-then-close mode after this long",
+        default_value = "10s"
+    )]
+    write_timeout: humantime::Duration,
+    #[structopt(
+        long = "detect-simultaneous",
+        help = "in shutdown:both mode, peek for the peer's FIN before shutting down"
+    )]
+    detect_simultaneous: bool,
+    #[structopt(
+        long = "mode-weights",
+        help = "pick the teardown mode per connection, e.g. `close-immediately=1,drain-then-close=2`, overrides the positional mode"
+    )]
+    mode_weights: Option<ModeWeights>,
+    #[structopt(
+        long = "close-listener-after",
+        help = "close the listening socket after accepting this many connections, then finish handling them"
+    )]
+    close_listener_after: Option<usize>,
+    #[structopt(
+        long = "accept-count",
+        help = "exit after accepting and handling this many connections"
+    )]
+    accept_count: Option<usize>,
+    #[structopt(
+        long = "exit-after-idle",
+        help = "exit once no connection was accepted or handled for this long (e.g. `5s`)"
+    )]
+    exit_after_idle: Option<humantime::Duration>,
+    #[structopt(
+        long = "server-threads",
+        help = "handle connections on a pool of this many worker threads instead of the accept loop",
+        raw(visible_alias = r#""concurrency""#)
+    )]
+    server_threads: Option<usize>,
+    #[structopt(
+        long = "max-lifetime",
+        help = "tear down connections open this long with the teardown mode, regardless of the protocol"
+    )]
+    max_lifetime: Option<humantime::Duration>,
+    #[structopt(
+        long = "dump-sockopts",
+        help = "log the effective socket options of accepted connections"
+    )]
+    dump_sockopts: bool,
+    #[structopt(
+        long = "cpu-affinity",
+        help = "pin connection handling threads to these cores, e.g. `0,1,2`, round-robin over pool workers"
+    )]
+    cpu_affinity: Option<CpuList>,
+    #[structopt(
+        long = "accept-delay",
+        help = "sleep this long before each accept, letting connections pile up in the accept queue"
+    )]
+    accept_delay: Option<humantime::Duration>,
+    #[structopt(
+        long = "proc-stats",
+        help = "log the connection's TCP state and queue sizes from /proc/net/tcp before teardown (Linux only)"
+    )]
+    proc_stats: bool,
+    #[structopt(
+        long = "tcp-info",
+        help = "log the connection's TCP_INFO (state, retransmits, RTT, queues) before and after the teardown action (Linux only)"
+    )]
+    tcp_info: bool,
+    #[structopt(
+        long = "fragment-echo",
+        help = "write the echo one byte at a time with TCP_NODELAY, so each byte is its own segment"
+    )]
+    fragment_echo: bool,
+    #[structopt(
+        long = "exec",
+        help = "hand each accepted connection to this shell command as its stdin and stdout, like inetd, instead of the teardown mode (Unix only)"
+    )]
+    exec: Option<String>,
+    #[structopt(
+        long = "workload",
+        help = "`numbers` (the odd number protocol) or `echo` (echo everything back until --echo-bytes or --echo-duration, then tear down)",
+        default_value = "numbers"
+    )]
+    workload: Workload,
+    #[structopt(
+        long = "echo-bytes",
+        help = "with --workload echo, tear down after echoing this many bytes"
+    )]
+    echo_bytes: Option<u64>,
+    #[structopt(
+        long = "echo-duration",
+        help = "with --workload echo, tear down after echoing for this long"
+    )]
+    echo_duration: Option<humantime::Duration>,
+    #[structopt(
+        long = "max-odd-wait",
+        help = "give up waiting for the odd number after this long and tear down without echo"
+    )]
+    max_odd_wait: Option<humantime::Duration>,
+    #[structopt(
+        long = "odd-wait-mode",
+        help = "teardown performed when --max-odd-wait expires",
+        default_value = "close-immediately"
+    )]
+    odd_wait_mode: TeardownMode,
+    #[structopt(
+        long = "report-out",
+        help = "write a CSV row per handled connection (teardown mode, outcome, evens before odd, drained bytes, close duration) to this file",
+        parse(from_os_str)
+    )]
+    report_out: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "pcap",
+        help = "capture the packets to and from the listen port into this pcap file and log each connection's SYN/FIN/RST/zero-window timeline (Linux only, needs CAP_NET_RAW)",
+        parse(from_os_str)
+    )]
+    pcap: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "shutdown-grace",
+        help = "on SIGINT or SIGTERM, stop accepting and wait this long for in-flight connections to finish their teardown before printing the summary and exiting (default: don't wait)"
+    )]
+    shutdown_grace: Option<humantime::Duration>,
+    #[structopt(
+        long = "verify-sequence",
+        help = "expect the client's --verify-sequence stream and append to the echo how many numbers were read in sequence and the teardown mode"
+    )]
+    verify_sequence: bool,
+    #[structopt(
+        long = "backend",
+        help = "`std` or `tokio`, with tokio only the teardown mode, --sleep, --linger and --endian apply",
+        default_value = "std"
+    )]
+    backend: Backend,
+    #[structopt(
+        long = "io-uring",
+        help = "drain and close connections through io_uring (requires the io-uring cargo feature)"
+    )]
+    io_uring: bool,
+    #[structopt(
+        long = "transport",
+        help = "`tcp` or `unix`, with unix the listen address is a socket path (or `@name` for Linux's abstract namespace) and only the teardown modes without TCP options, --sleep, --framing, --compress, --endian and --verify-sequence apply",
+        default_value = "tcp"
+    )]
+    transport: Transport,
+    #[structopt(
+        long = "tls",
+        help = "speak TLS with a self-signed certificate generated at startup, only close-immediately, reset-immediately, sleep-then-close, --framing, --endian and --verify-sequence apply"
+    )]
+    tls: bool,
+    #[structopt(
+        long = "tls-close",
+        help = "with --tls, `close-notify` or `abrupt`: whether close_notify precedes the teardown mode",
+        default_value = "close-notify"
+    )]
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    tls_close: TlsClose,
+}
+
+/// The server's teardown mode argument.
+#[derive(Clone, Copy)]
+enum ServerMode {
+    Fixed(TeardownMode),
+    /// `random`, a mode picked per connection
+    Random,
+}
+
+impl std::str::FromStr for ServerMode {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(ServerMode::Random),
+            s => s.parse().map(ServerMode::Fixed),
+        }
+    }
+}
+
+/// Weighted set of teardown modes to sample from, parsed from `mode=weight,...`.
+struct ModeWeights(Vec<(TeardownMode, u32)>);
+
+impl std::str::FromStr for ModeWeights {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Vec::new();
+        for entry in s.split(',') {
+            let mut parts = entry.splitn(2, '=');
+            let mode = parts.next().unwrap_or_default();
+            let weight = parts
+                .next()
+                .ok_or_else(|| failure::format_err!("expected mode=weight, got {:?}", entry))?;
+            let mode = mode
+                .parse::<TeardownMode>()
+                .with_context(|_| format!("invalid teardown mode {:?}", mode))?;
+            let weight = weight
+                .parse::<u32>()
+                .with_context(|_| format!("invalid weight {:?}", weight))?;
+            weights.push((mode, weight));
+        }
+        if weights
+            .iter()
+            .map(|(_, weight)| u64::from(*weight))
+            .sum::<u64>()
+            == 0
+        {
+            failure::bail!("mode weights must not all be zero");
+        }
+        Ok(ModeWeights(weights))
+    }
+}
+
+impl ModeWeights {
+    fn sample<R: rand::Rng>(&self, rng: &mut R) -> TeardownMode {
+        let total: u64 = self.0.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        let mut pick = rng.random_range(0..total);
+        for (mode, weight) in &self.0 {
+            if pick < u64::from(*weight) {
+                return *mode;
+            }
+            pick -= u64::from(*weight);
+        }
+        unreachable!("pick is below the total weight")
+    }
+}
+
+/// Distribution to draw per-connection sleep durations from.
+enum SleepDist {
+    /// `uniform:MIN:MAX`
+    Uniform(std::time::Duration, std::time::Duration),
+    /// `exp:MEAN`, exponentially distributed
+    Exp(std::time::Duration),
+}
+
+impl std::str::FromStr for SleepDist {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let duration = |d: &str| {
+            d.parse::<humantime::Duration>()
+                .map(Into::into)
+                .with_context(|_| format!("invalid duration {:?}", d))
+        };
+        let parts: Vec<_> = s.split(':').collect();
+        match parts[..] {
+            ["uniform", min, max] => {
+                let (min, max) = (duration(min)?, duration(max)?);
+                if min > max {
+                    failure::bail!("uniform minimum {:?} exceeds maximum {:?}", min, max);
+                }
+                Ok(SleepDist::Uniform(min, max))
+            }
+            ["exp", mean] => Ok(SleepDist::Exp(duration(mean)?)),
+            _ => failure::bail!("expected `uniform:MIN:MAX` or `exp:MEAN`, got {:?}", s),
+        }
+    }
+}
+
+impl SleepDist {
+    fn sample<R: rand::Rng>(&self, rng: &mut R) -> std::time::Duration {
+        match *self {
+            SleepDist::Uniform(min, max) => rng.random_range(min..=max),
+            SleepDist::Exp(mean) => {
+                // inverse transform sampling, 1 - u is in (0, 1]
+                let u: f64 = rng.random();
+                mean.mul_f64(-(1.0 - u).ln())
+            }
+        }
+    }
+}
+
+/// How a `--tls` server ends the TLS session before the teardown mode.
+#[derive(Clone, Copy, EnumString, Display)]
+#[strum(serialize_all = "kebab_case")]
+enum TlsClose {
+    /// send close_notify, a clean TLS EOF for the client
+    CloseNotify,
+    /// leave it to the TCP teardown, a truncation for the client
+    Abrupt,
+}
+
+/// apply `op` to the std socket under a tokio connection
+///
+/// For socket options and shutdowns tokio doesn't offer, like `SO_LINGER`
+/// (deprecated there) or shutting down the read half.
+#[cfg(feature = "tokio")]
+fn with_std_conn<T>(
+    conn: tokio::net::TcpStream,
+    op: impl FnOnce(&TcpStream) -> io::Result<T>,
+) -> io::Result<(tokio::net::TcpStream, T)> {
+    let conn = conn.into_std()?;
+    let res = op(&conn)?;
+    Ok((tokio::net::TcpStream::from_std(conn)?, res))
+}
+
+/// Durations of the phases of a single server-side connection.
+///
+/// A phase that was not reached is `None`.
+#[derive(Debug, Default)]
+pub struct ConnTimings {
+    /// reading numbers until the first odd one
+    read_until_odd: Option<std::time::Duration>,
+    /// writing the odd number back to the client
+    write_response: Option<std::time::Duration>,
+    /// draining the connection until EOF
+    drain: Option<std::time::Duration>,
+    /// from the written response until EOF, i.e. how long the client kept sending
+    response_to_eof: Option<std::time::Duration>,
+    /// from our `shutdown(Write)` until EOF, i.e. how long the client took to
+    /// notice our FIN and shut down its own write side
+    shutdown_to_eof: Option<std::time::Duration>,
+    /// the final drop of the connection
+    pub close: Option<std::time::Duration>,
+    /// bytes read by draining until EOF, not a duration but measured alongside
+    pub drained_bytes: Option<u64>,
+    /// bytes the client hadn't acknowledged yet when the teardown started
+    pub unsent_at_teardown: Option<u64>,
+    /// bytes received but not read by us when the teardown started
+    pub unread_at_teardown: Option<u64>,
+}
+
+/// What `--report-out` records about a single server-side connection.
+#[derive(Default)]
+pub struct ConnReport {
+    /// the teardown mode chosen for the connection
+    pub mode: Option<TeardownMode>,
+    evens_before_odd: Option<u64>,
+    /// from `accept` returning to the handler picking the connection up
+    accept_to_handle: Option<std::time::Duration>,
+    pub timings: ConnTimings,
+}
+
+/// How the handling of a single connection ended.
+#[derive(Debug)]
+pub enum ConnOutcome {
+    /// the protocol ran to completion and the connection was torn down
+    Completed {
+        /// how many even numbers were read before the odd one, if it was read
+        evens_before_odd: Option<u64>,
+    },
+    /// the client violated the protocol (e.g. closed before sending an odd number),
+    /// the connection was dropped without teardown
+    ProtocolError,
+    /// the client closed or reset the connection before we could echo the odd number,
+    /// the connection was dropped without teardown
+    ClientGoneBeforeEcho,
+    /// `--max-lifetime` expired before the odd number was read, the connection
+    /// was torn down anyway
+    LifetimeExpired,
+    /// `--max-odd-wait` expired before the odd number was read, the connection
+    /// was torn down with `--odd-wait-mode`
+    OddWaitExpired,
+    /// a socket operation failed, e.g. the client reset the connection while
+    /// we drained it, the connection was dropped where it failed
+    TeardownError(io::ErrorKind),
+}
+
+impl ConnOutcome {
+    /// the outcome of a connection whose handling failed with `e`
+    fn failed(e: &failure::Error) -> Self {
+        let kind = e
+            .iter_chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map_or(io::ErrorKind::Other, io::Error::kind);
+        ConnOutcome::TeardownError(kind)
+    }
+}
+
+/// the variant name, with the error kind for `TeardownError`
+impl std::fmt::Display for ConnOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConnOutcome::Completed { .. } => f.write_str("Completed"),
+            ConnOutcome::TeardownError(kind) => write!(f, "TeardownError({:?})", kind),
+            outcome => write!(f, "{:?}", outcome),
+        }
+    }
+}
+
+/// Why a connection was torn down before its odd number was read.
+#[derive(Clone, Copy)]
+enum Expiry {
+    Lifetime,
+    OddWait,
+}
+
+/// Ends reads on a connection once it has been open for `--max-lifetime`.
+///
+/// The watcher thread shuts down the read half, so blocked reads return EOF
+/// and the handler can tell the expiry from a protocol error via `expired`.
+struct LifetimeWatcher {
+    expired: Arc<AtomicBool>,
+    /// dropping this stops the watcher
+    _done: crossbeam_channel::Sender<()>,
+}
+
+impl LifetimeWatcher {
+    fn start(conn: &TcpStream, lifetime: std::time::Duration) -> io::Result<Self> {
+        let conn = conn.try_clone()?;
+        let expired = Arc::new(AtomicBool::new(false));
+        let (done_tx, done_rx) = crossbeam_channel::bounded::<()>(0);
+        {
+            let expired = expired.clone();
+            std::thread::spawn(move || {
+                if let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                    done_rx.recv_timeout(lifetime)
+                {
+                    let _span = ConnLogSpan::enter(conn.peer_addr(), conn.local_addr());
+                    log::info!("connection lifetime {:?} expired", lifetime);
+                    expired.store(true, atomic::Ordering::SeqCst);
+                    if let Err(e) = conn.shutdown(net::Shutdown::Read) {
+                        log::warn!("cannot shut down read half on lifetime expiry: {:?}", e);
+                    }
+                }
+            });
+        }
+        Ok(LifetimeWatcher {
+            expired,
+            _done: done_tx,
+        })
+    }
+
+    fn expired(&self) -> bool {
+        self.expired.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// CSV timeline of per-connection socket events, one row per event.
+///
+/// Timestamps are nanoseconds since the UNIX epoch, derived from a monotonic
+/// clock anchored at creation, so they never go backwards but can still be
+/// aligned with an external packet capture.
+struct EventLog {
+    out: Option<Mutex<io::LineWriter<std::fs::File>>>,
+    start: std::time::Instant,
+    start_unix_ns: u128,
+}
+
+/// `EventLog` bound to a single connection.
+struct ConnEvents<'a> {
+    log: &'a EventLog,
+    conn: String,
+}
+
+/// Labels of the connections currently in `serve_conn`.
+#[derive(Default)]
+struct InFlight(Mutex<std::collections::BTreeSet<String>>);
+
+impl InFlight {
+    fn enter(&self, label: &str) {
+        self.0.lock().unwrap().insert(label.to_string());
+    }
+
+    fn leave(&self, label: &str) {
+        self.0.lock().unwrap().remove(label);
+    }
+
+    fn count(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Stops `Server::serve` early, on Ctrl-C or `RunningServer::stop`.
+pub struct Interrupt {
+    rx: crossbeam_channel::Receiver<()>,
+    /// where to send the error naming the connections still running after
+    /// `--shutdown-grace`; the process exits instead if `None`
+    aborted: Option<crossbeam_channel::Sender<failure::Error>>,
+}
+
+/// CSV file of `ConnReport`s, one row per connection as its handling ends.
+pub struct ReportLog {
+    out: Option<Mutex<csv::Writer<std::fs::File>>>,
+    /// totals of the reports so far, shared by all workers so it survives an
+    /// interrupt that aborts connections still in flight
+    summary: Mutex<ServerSummary>,
+}
+
+impl ReportLog {
+    /// create a report writing to `path`, or discarding rows if `None`
+    pub fn open(path: Option<&std::path::Path>) -> Result<Self, failure::Error> {
+        let out = match path {
+            Some(path) => {
+                let mut out = csv::Writer::from_path(path)
+                    .with_context(|_| format!("create report file {:?}", path))?;
+                out.write_record([
+                    "conn",
+                    "mode",
+                    "outcome",
+                    "evens_before_odd",
+                    "drained_bytes",
+                    "close_ns",
+                    "accept_to_handle_ns",
+                    "unsent_bytes",
+                    "unread_bytes",
+                ])
+                .and_then(|()| out.flush().map_err(Into::into))
+                .context("write report header")?;
+                Some(Mutex::new(out))
+            }
+            None => None,
+        };
+        Ok(ReportLog {
+            out,
+            summary: Mutex::new(ServerSummary::default()),
+        })
+    }
+
+    fn record(&self, conn: &str, report: &ConnReport, outcome: &ConnOutcome) {
+        self.summary.lock().unwrap().record(report, outcome);
+        let out = match &self.out {
+            Some(out) => out,
+            None => return,
+        };
+        let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        let nanos = |d: Option<std::time::Duration>| {
+            d.map(|d| d.as_nanos().to_string()).unwrap_or_default()
+        };
+        let mut out = out.lock().unwrap();
+        // flush every row, so the report is complete whenever the server stops
+        let res = out
+            .write_record([
+                conn.to_string(),
+                report.mode.map(|m| m.to_string()).unwrap_or_default(),
+                outcome.to_string(),
+                opt(report.evens_before_odd),
+                opt(report.timings.drained_bytes),
+                nanos(report.timings.close),
+                nanos(report.accept_to_handle),
+                opt(report.timings.unsent_at_teardown),
+                opt(report.timings.unread_at_teardown),
+            ])
+            .and_then(|()| out.flush().map_err(Into::into));
+        if let Err(e) = res {
+            log::error!("cannot write report row: {:?}", e);
+        }
+    }
+}
+
+impl EventLog {
+    /// create a log writing to `path`, or discarding events if `None`
+    fn open(path: Option<&std::path::Path>) -> Result<Self, failure::Error> {
+        let out = match path {
+            Some(path) => {
+                let file = std::fs::File::create(path)
+                    .with_context(|_| format!("create events file {:?}", path))?;
+                let mut out = io::LineWriter::new(file);
+                writeln!(out, "conn,event,t_ns").context("write events header")?;
+                Some(Mutex::new(out))
+            }
+            None => None,
+        };
+        let start_unix_ns = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .context("system time before UNIX epoch")?
+            .as_nanos();
+        Ok(EventLog {
+            out,
+            start: std::time::Instant::now(),
+            start_unix_ns,
+        })
+    }
+
+    fn conn(&self, conn: &TcpStream) -> ConnEvents<'_> {
+        ConnEvents {
+            log: self,
+            conn: conn_label(conn.peer_addr(), conn.local_addr()),
+        }
+    }
+}
+
+impl ConnEvents<'_> {
+    fn record(&self, event: &str) {
+        let out = match &self.log.out {
+            Some(out) => out,
+            None => return,
+        };
+        let t_ns = self.log.start_unix_ns + self.log.start.elapsed().as_nanos();
+        let mut out = out.lock().unwrap();
+        if let Err(e) = writeln!(out, "{},{},{}", self.conn, event, t_ns) {
+            log::warn!("cannot write event {:?}: {:?}", event, e);
+        }
+    }
+}
+
+impl Server {
+    pub fn run(&self) -> Result<(), failure::Error> {
+        if self.tls && !cfg!(feature = "tls") {
+            failure::bail!("--tls requires building with the tls cargo feature");
+        }
+        if matches!(self.backend, Backend::Tokio) && !cfg!(feature = "tokio") {
+            failure::bail!("--backend tokio requires building with the tokio cargo feature");
+        }
+        if (self.drain_limit.is_some() || self.drain_timeout.is_some())
+            && (self.io_uring
+                || self.tls
+                || !matches!(self.backend, Backend::Std)
+                || !matches!(self.transport, Transport::Tcp))
+        {
+            failure::bail!(
+                "--drain-limit and --drain-timeout need --backend std over plain TCP, without --io-uring"
+            );
+        }
+        if let Transport::Unix = self.transport {
+            return self.serve_unix();
+        }
+        let addr = net::ToSocketAddrs::to_socket_addrs(&self.listen)
+            .context("resolve listen address")?
+            .next()
+            .ok_or_else(|| failure::format_err!("listen address resolved to nothing"))?;
+        let listener = self.bind(addr)?;
+        log::info!(
+            "listening on {:?} with backlog {}",
+            listener.local_addr(),
+            self.backlog
+        );
+        if let Some(path) = &self.pcap {
+            capture::start(
+                path,
+                listener.local_addr().context("listen address")?.port(),
+            )?;
+        }
+        if (self.accept_count.is_some() || self.exit_after_idle.is_some())
+            && (self.tls || !matches!(self.backend, Backend::Std))
+        {
+            failure::bail!(
+                "--accept-count and --exit-after-idle need --backend std, without --tls"
+            );
+        }
+        if self.tls {
+            #[cfg(feature = "tls")]
+            return self.serve_tls(listener);
+            #[cfg(not(feature = "tls"))]
+            unreachable!("rejected by Server::run");
+        }
+        match self.backend {
+            Backend::Std => {
+                let reports = ReportLog::open(self.report_out.as_deref())?;
+                let (interrupt_tx, interrupt_rx) = crossbeam_channel::bounded::<()>(1);
+                ctrlc::set_handler(move || {
+                    let _ = interrupt_tx.try_send(());
+                })
+                .context("install signal handler")?;
+                let interrupt = Interrupt {
+                    rx: interrupt_rx,
+                    aborted: None,
+                };
+                let res = self.serve(listener, self.accept_count, &reports, Some(interrupt));
+                print!("{}", reports.summary.lock().unwrap().table());
+                res.map(|_| ())
+            }
+            #[cfg(feature = "tokio")]
+            Backend::Tokio => self.serve_tokio(listener),
+            #[cfg(not(feature = "tokio"))]
+            Backend::Tokio => unreachable!("rejected by Server::run"),
+        }
+    }
+
+    /// the listening socket, with the socket options set before it listens
+    fn bind(&self, addr: net::SocketAddr) -> Result<net::TcpListener, failure::Error> {
+        let socket = tcp_socket(addr).context("create socket")?;
+        if addr.is_ipv6() {
+            // explicit, the system default (net.ipv6.bindv6only) varies
+            socket
+                .set_only_v6(self.v6_only)
+                .context("set IPV6_V6ONLY")?;
+        }
+        // same as std's TcpListener::bind
+        socket.set_reuse_address(true).context("reuse address")?;
+        // accepted connections inherit the options below from the listening socket
+        self.keepalive.apply(&socket).context("set keepalive")?;
+        set_buffer_sizes(&socket, self.sndbuf, self.rcvbuf).context("set buffer sizes")?;
+        if let Some(timeout) = self.tcp_user_timeout {
+            set_user_timeout(&socket, *timeout).context("set TCP_USER_TIMEOUT")?;
+        }
+        if self.nodelay {
+            socket.set_tcp_nodelay(true).context("set nodelay")?;
+        }
+        for opt in &self.sockopts {
+            opt.apply(&socket)
+                .with_context(|_| format!("set --sockopt {}", opt))?;
+        }
+        socket.bind(&addr.into()).context("bind")?;
+        socket.listen(self.backlog).context("listen")?;
+        Ok(net::TcpListener::from(socket))
+    }
+
+    /// accept & handle connections, returning after `accept_limit` connections if given,
+    /// or after `interrupt` fired and `--shutdown-grace` passed
+    pub fn serve(
+        &self,
+        listener: net::TcpListener,
+        accept_limit: Option<usize>,
+        reports: &ReportLog,
+        interrupt: Option<Interrupt>,
+    ) -> Result<ServerStats, failure::Error> {
+        if let TeardownMode::CloseAfterNEvenNumbers = self.close_after_mode {
+            failure::bail!("--close-after-mode must not be close-after-n-even-numbers");
+        }
+        if let TeardownMode::CloseAfterNEvenNumbers = self.odd_wait_mode {
+            failure::bail!("--odd-wait-mode must not be close-after-n-even-numbers");
+        }
+        match self.flood_mode {
+            TeardownMode::CloseAfterNEvenNumbers | TeardownMode::FloodThenClose => {
+                failure::bail!("--flood-mode must be a plain teardown mode")
+            }
+            _ => {}
+        }
+        if let Workload::Echo = self.workload {
+            if self.verify_sequence || self.max_odd_wait.is_some() {
+                failure::bail!(
+                    "--workload echo reads no numbers, without --verify-sequence or --max-odd-wait"
+                );
+            }
+            if !matches!(self.compress, Compress::None) {
+                failure::bail!("--workload echo does not support --compress");
+            }
+        } else if self.echo_bytes.is_some() || self.echo_duration.is_some() {
+            failure::bail!("--echo-bytes and --echo-duration require --workload echo");
+        }
+        if self.server_threads == Some(0) {
+            failure::bail!("--server-threads must be at least 1");
+        }
+        if self.io_uring && !cfg!(feature = "io-uring") {
+            failure::bail!("--io-uring requires building with the io-uring cargo feature");
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            cpus.validate()?;
+        }
+        let events = EventLog::open(self.events_out.as_deref())?;
+        let rng = Mutex::new(match self.seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_rng(&mut rand::rng()),
+        });
+        let workers = self.server_threads.unwrap_or(0);
+        // connect target that wakes up the accept loop on shutdown
+        let wake_addr = listener.local_addr().ok().map(|mut addr| {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    net::SocketAddr::V4(_) => net::Ipv4Addr::LOCALHOST.into(),
+                    net::SocketAddr::V6(_) => net::Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            addr
+        });
+        let shutdown = AtomicBool::new(false);
+        let in_flight = InFlight::default();
+        // last accept or finished connection, for `--exit-after-idle`
+        let last_active = Mutex::new(std::time::Instant::now());
+        // accepted connections waiting for a worker
+        let (conns_tx, conns_rx) =
+            crossbeam_channel::bounded::<(TcpStream, std::time::Instant)>(workers);
+        let mut stats = ServerStats::default();
+        std::thread::scope(|scope| {
+            let events = &events;
+            let rng = &rng;
+            let shutdown = &shutdown;
+            let in_flight = &in_flight;
+            let last_active = &last_active;
+            let (serve_done_tx, serve_done_rx) = crossbeam_channel::bounded::<()>(0);
+            if let Some(idle) = self.exit_after_idle {
+                let serve_done_rx = serve_done_rx.clone();
+                scope.spawn(move || {
+                    Self::shutdown_when_idle(
+                        *idle,
+                        last_active,
+                        serve_done_rx,
+                        shutdown,
+                        in_flight,
+                        wake_addr,
+                    )
+                });
+            }
+            if let Some(interrupt) = interrupt {
+                let grace = self
+                    .shutdown_grace
+                    .map_or(std::time::Duration::ZERO, |g| *g);
+                scope.spawn(move || {
+                    Self::shutdown_on_interrupt(
+                        grace,
+                        interrupt,
+                        serve_done_rx,
+                        shutdown,
+                        in_flight,
+                        wake_addr,
+                        reports,
+                    )
+                });
+            }
+            let worker_handles: Vec<_> = (0..workers)
+                .map(|i| {
+                    let conns_rx = conns_rx.clone();
+                    scope.spawn(move || -> Result<ServerStats, failure::Error> {
+                        if let Some(cpus) = &self.cpu_affinity {
+                            cpus.pin_current_thread(i);
+                        }
+                        let mut stats = ServerStats::default();
+                        for (conn, accepted_at) in conns_rx.iter() {
+                            if shutdown.load(atomic::Ordering::SeqCst) {
+                                log::info!("shutting down, dropping queued connection {:?}", conn);
+                                continue;
+                            }
+                            let label = conn_label(conn.peer_addr(), conn.local_addr());
+                            in_flight.enter(&label);
+                            self.serve_conn(conn, accepted_at, events, reports, rng, &mut stats);
+                            in_flight.leave(&label);
+                            *last_active.lock().unwrap() = std::time::Instant::now();
+                        }
+                        Ok(stats)
+                    })
+                })
+                .collect();
+            // Periodically report how many connections wait for a worker,
+            // i.e. whether the workers are saturated, until the accept loop is done.
+            let (accept_done_tx, accept_done_rx) = crossbeam_channel::bounded::<()>(0);
+            if workers > 0 {
+                let conns_rx = conns_rx.clone();
+                scope.spawn(move || {
+                    while let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                        accept_done_rx.recv_timeout(std::time::Duration::from_secs(1))
+                    {
+                        if !conns_rx.is_empty() {
+                            log::info!("connection queue depth: {}/{}", conns_rx.len(), workers);
+                        }
+                    }
+                });
+            }
+            drop(conns_rx);
+            // without a pool, the accept loop handles the connections itself
+            if workers == 0 {
+                if let Some(cpus) = &self.cpu_affinity {
+                    cpus.pin_current_thread(0);
+                }
+            }
+
+            let mut listener = Some(listener);
+            while accept_limit.is_none_or(|limit| stats.accepted < limit) {
+                if shutdown.load(atomic::Ordering::SeqCst) {
+                    log::info!("shutting down, not accepting any more connections");
+                    break;
+                }
+                let conn = match &listener {
+                    Some(listener) => {
+                        if let Some(delay) = self.accept_delay {
+                            log::info!("delaying accept by {}", delay);
+                            spin_sleep::sleep(delay.into());
+                        }
+                        log::info!("accepting connection");
+                        listener.incoming().next().unwrap()
+                    }
+                    None => {
+                        log::info!("listener closed, not accepting any more connections");
+                        break;
+                    }
+                };
+                let accepted_at = std::time::Instant::now();
+                if shutdown.load(atomic::Ordering::SeqCst) {
+                    // the wake-up connection, or a client that raced it
+                    log::info!("shutting down, dropping connection accepted during shutdown");
+                    break;
+                }
+                match conn.context("accept") {
+                    Ok(conn) => {
+                        stats.accepted += 1;
+                        *last_active.lock().unwrap() = accepted_at;
+                        let _span = ConnLogSpan::enter(conn.peer_addr(), conn.local_addr());
+                        events.conn(&conn).record("accept");
+                        log::info!("accepted connection {:?}", conn);
+                        if self.close_listener_after == Some(stats.accepted) {
+                            // connections still in the accept queue are reset,
+                            // the accepted one must survive
+                            log::info!("closing listener after {} connections", stats.accepted);
+                            drop(listener.take());
+                            events.conn(&conn).record("listener-close");
+                        }
+                        if workers == 0 {
+                            let label = conn_label(conn.peer_addr(), conn.local_addr());
+                            in_flight.enter(&label);
+                            self.serve_conn(conn, accepted_at, events, reports, rng, &mut stats);
+                            in_flight.leave(&label);
+                            *last_active.lock().unwrap() = std::time::Instant::now();
+                        } else if conns_tx.send((conn, accepted_at)).is_err() {
+                            log::error!("all workers failed, not accepting any more connections");
+                            break;
+                        }
+                    }
+                    Err(e) => log::error!("accept error: {:?}", e),
+                }
+            }
+            drop(conns_tx);
+            drop(accept_done_tx);
+
+            for worker in worker_handles {
+                stats.merge(worker.join().expect("worker thread panicked")?);
+            }
+            drop(serve_done_tx);
+            Ok::<_, failure::Error>(())
+        })?;
+        log::info!("server stats: {:?}", stats);
+        Ok(stats)
+    }
+
+    /// once no connection was accepted or in flight for `idle`, stop the accept
+    /// loop; returns early once `serve_done` is closed
+    fn shutdown_when_idle(
+        idle: std::time::Duration,
+        last_active: &Mutex<std::time::Instant>,
+        serve_done: crossbeam_channel::Receiver<()>,
+        shutdown: &AtomicBool,
+        in_flight: &InFlight,
+        wake_addr: Option<net::SocketAddr>,
+    ) {
+        loop {
+            let idle_for = last_active.lock().unwrap().elapsed();
+            // a running connection counts as activity until it finishes
+            let wait = if in_flight.count() > 0 {
+                idle
+            } else if idle_for >= idle {
+                break;
+            } else {
+                idle - idle_for
+            };
+            match serve_done.recv_timeout(wait) {
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        }
+        log::info!("idle for {:?}, shutting down", idle);
+        shutdown.store(true, atomic::Ordering::SeqCst);
+        // the accept loop is blocked in accept
+        if let Some(addr) = wake_addr {
+            let _ = TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(1));
+        }
+    }
+
+    /// on `interrupt`, stop the accept loop and give in-flight connections `grace` to finish;
+    /// if they don't, report them to `interrupt.aborted` or exit the process without one.
+    /// Returns early once `serve_done` is closed.
+    fn shutdown_on_interrupt(
+        grace: std::time::Duration,
+        interrupt: Interrupt,
+        serve_done: crossbeam_channel::Receiver<()>,
+        shutdown: &AtomicBool,
+        in_flight: &InFlight,
+        wake_addr: Option<net::SocketAddr>,
+        reports: &ReportLog,
+    ) {
+        crossbeam_channel::select! {
+            recv(interrupt.rx) -> _ => {}
+            recv(serve_done) -> _ => return,
+        }
+        shutdown.store(true, atomic::Ordering::SeqCst);
+        let running = in_flight.count();
+        log::info!(
+            "interrupted, {} connections still being handled, waiting up to {:?}",
+            running,
+            grace
+        );
+        // the accept loop may be blocked in accept
+        if let Some(addr) = wake_addr {
+            let _ = TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(1));
+        }
+        let deadline = std::time::Instant::now() + grace;
+        while in_flight.count() > 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let remaining = in_flight.labels();
+        let finished = running.saturating_sub(remaining.len());
+        if !remaining.is_empty() {
+            log::error!(
+                "{} of {} connections finished within grace, aborting {} still running: {}",
+                finished,
+                running,
+                remaining.len(),
+                remaining.join(", ")
+            );
+            match interrupt.aborted {
+                Some(aborted) => {
+                    // the connections' threads keep running until they end on their own
+                    let _ = aborted.send(failure::format_err!(
+                        "{} connections still running after {:?} shutdown grace: {}",
+                        remaining.len(),
+                        grace,
+                        remaining.join(", ")
+                    ));
+                    return;
+                }
+                None => {
+                    print!("{}", reports.summary.lock().unwrap().table());
+                    std::process::exit(1);
+                }
+            }
+        }
+        log::info!(
+            "{} of {} connections finished within grace",
+            finished,
+            running
+        );
+    }
+
+    /// the positional teardown mode, for the paths that cannot pick one per connection
+    fn fixed_mode(&self, path: &str) -> Result<TeardownMode, failure::Error> {
+        match self.teardown_mode {
+            ServerMode::Fixed(mode) => Ok(mode),
+            ServerMode::Random => failure::bail!("{} does not support teardown mode random", path),
+        }
+    }
+
+    fn numbers_workload(&self, path: &str) -> Result<(), failure::Error> {
+        match self.workload {
+            Workload::Numbers => Ok(()),
+            Workload::Echo => failure::bail!("{} does not support --workload echo", path),
+        }
+    }
+
+    /// accept connections forever and handle each in a task on the tokio runtime
+    #[cfg(feature = "tokio")]
+    fn serve_tokio(&self, listener: net::TcpListener) -> Result<(), failure::Error> {
+        let teardown_mode = self.fixed_mode("--backend tokio")?;
+        self.numbers_workload("--backend tokio")?;
+        match teardown_mode {
+            TeardownMode::CloseAfterNEvenNumbers | TeardownMode::FloodThenClose => {
+                failure::bail!("--backend tokio does not support {}", teardown_mode)
+            }
+            _ => {}
+        }
+        if !matches!(self.framing, Framing::Numbers)
+            || !matches!(self.word_size, WordSize::U32)
+            || !matches!(self.compress, Compress::None)
+            || self.mode_weights.is_some()
+            || self.io_uring
+        {
+            failure::bail!(
+                "--backend tokio needs --framing numbers, --word-size 4, no --compress, no --mode-weights and no --io-uring"
+            );
+        }
+        let (mode, sleep, linger, endian) = (
+            teardown_mode,
+            *self.sleep,
+            self.linger.map(|l| *l),
+            self.endian,
+        );
+        tokio_runtime().block_on(async move {
+            listener.set_nonblocking(true).context("set nonblocking")?;
+            let listener =
+                tokio::net::TcpListener::from_std(listener).context("register listener")?;
+            loop {
+                log::info!("accepting connection");
+                let (conn, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        log::error!("accept error: {:?}", e);
+                        continue;
+                    }
+                };
+                log::info!("accepted connection from {}", peer);
+                tokio::spawn(async move {
+                    match Self::handle_conn_tokio(conn, mode, sleep, linger, endian).await {
+                        Ok(()) => log::info!("closed connection from {}", peer),
+                        Err(e) => log::warn!("connection from {} failed: {:?}", peer, e),
+                    }
+                });
+            }
+        })
+    }
+
+    /// accept connections on the Unix domain socket at `--listen`, each handled on its own thread
+    fn serve_unix(&self) -> Result<(), failure::Error> {
+        let teardown_mode = self.fixed_mode("--transport unix")?;
+        self.numbers_workload("--transport unix")?;
+        match teardown_mode {
+            TeardownMode::CloseImmediately
+            | TeardownMode::SleepThenClose
+            | TeardownMode::DrainThenClose
+            | TeardownMode::ShutdownWriteThenDrain
+            | TeardownMode::Shutdown { .. }
+            | TeardownMode::DoubleShutdownWrite => {}
+            mode => failure::bail!("{} has no equivalent on Unix domain sockets", mode),
+        }
+        if self.mode_weights.is_some()
+            || self.io_uring
+            || self.pcap.is_some()
+            || self.keepalive.keepalive
+            || self.tcp_user_timeout.is_some()
+            || self.nodelay
+            || self.sndbuf.is_some()
+            || self.rcvbuf.is_some()
+            || !self.sockopts.is_empty()
+            || matches!(self.backend, Backend::Tokio)
+        {
+            failure::bail!(
+                "--transport unix needs --backend std, no --mode-weights, no --io-uring, no --pcap and none of the TCP socket options"
+            );
+        }
+        let addr = unix_addr(&self.listen).context("parse listen address")?;
+        // a socket left behind by an earlier run would fail the bind
+        if let Some(path) = addr.as_pathname() {
+            if let Ok(meta) = std::fs::symlink_metadata(path) {
+                if std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()) {
+                    std::fs::remove_file(path).context("remove stale socket")?;
+                }
+            }
+        }
+        let listener = UnixListener::bind_addr(&addr).context("bind")?;
+        log::info!("listening on {}", self.listen);
+        let conn_params = (
+            teardown_mode,
+            *self.sleep,
+            (self.framing, self.word_size),
+            self.compress,
+            self.endian,
+            self.verify_sequence,
+        );
+        for conn in listener.incoming() {
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("accept error: {:?}", e);
+                    continue;
+                }
+            };
+            log::info!("accepted connection");
+            std::thread::spawn(move || match Self::handle_conn_unix(conn, conn_params) {
+                Ok(()) => log::info!("closed connection"),
+                Err(e) => log::warn!("connection failed: {:?}", e),
+            });
+        }
+        Ok(())
+    }
+
+    /// accept TLS connections, each handled on its own thread
+    #[cfg(feature = "tls")]
+    fn serve_tls(&self, listener: net::TcpListener) -> Result<(), failure::Error> {
+        let teardown_mode = self.fixed_mode("--tls")?;
+        self.numbers_workload("--tls")?;
+        match teardown_mode {
+            TeardownMode::CloseImmediately
+            | TeardownMode::ResetImmediately
+            | TeardownMode::SleepThenClose => {}
+            mode => failure::bail!("--tls does not support {}", mode),
+        }
+        if !matches!(self.compress, Compress::None)
+            || self.mode_weights.is_some()
+            || self.io_uring
+            || matches!(self.backend, Backend::Tokio)
+        {
+            failure::bail!(
+                "--tls needs --backend std, no --compress, no --mode-weights and no --io-uring"
+            );
+        }
+        let config = tls::server_config()?;
+        let conn_params = (
+            teardown_mode,
+            *self.sleep,
+            (self.framing, self.word_size),
+            self.endian,
+            self.verify_sequence,
+            self.tls_close,
+        );
+        for conn in listener.incoming() {
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("accept error: {:?}", e);
+                    continue;
+                }
+            };
+            let config = config.clone();
+            std::thread::spawn(move || {
+                let _span = ConnLogSpan::enter(conn.peer_addr(), conn.local_addr());
+                let (local, peer) = (conn.local_addr().ok(), conn.peer_addr().ok());
+                log::info!("accepted connection");
+                match Self::handle_conn_tls(conn, config, conn_params) {
+                    Ok(()) => log::info!("closed connection"),
+                    Err(e) => log::warn!("connection failed: {:?}", e),
+                }
+                capture::log_timeline(local, peer);
+            });
+        }
+        Ok(())
+    }
+
+    /// the TLS counterpart of `handle_conn`
+    #[cfg(feature = "tls")]
+    fn handle_conn_tls(
+        conn: TcpStream,
+        config: std::sync::Arc<rustls::ServerConfig>,
+        (mode, sleep, framing, endian, verify_sequence, tls_close): (
+            TeardownMode,
+            std::time::Duration,
+            (Framing, WordSize),
+            Endian,
+            bool,
+            TlsClose,
+        ),
+    ) -> Result<(), failure::Error> {
+        let session = rustls::ServerConnection::new(config).context("set up TLS session")?;
+        let mut stream = rustls::StreamOwned::new(session, conn);
+        let (odd, evens, in_sequence) = Self::read_until_odd(
+            &mut io::BufReader::new(&mut stream),
+            framing,
+            endian,
+            verify_sequence,
+        )?;
+        log::info!(
+            "client sent odd number {:?} after {:?} even numbers",
+            odd,
+            evens
+        );
+        let response = Self::encode_response(endian, odd, verify_sequence, in_sequence, mode);
+        stream
+            .write_all(&response)
+            .and_then(|()| stream.flush())
+            .context("write odd number to connection")?;
+
+        match tls_close {
+            TlsClose::CloseNotify => {
+                stream.conn.send_close_notify();
+                while stream.conn.wants_write() {
+                    stream
+                        .conn
+                        .write_tls(&mut stream.sock)
+                        .context("send close_notify")?;
+                }
+                log::info!("sent close_notify");
+            }
+            TlsClose::Abrupt => log::info!("not sending close_notify"),
+        }
+        let conn = stream.sock;
+        match mode {
+            TeardownMode::CloseImmediately => {}
+            TeardownMode::ResetImmediately => {
+                log::info!("setting linger to zero");
+                socket2::SockRef::from(&conn)
+                    .set_linger(Some(std::time::Duration::from_secs(0)))
+                    .context("set linger")?;
+            }
+            TeardownMode::SleepThenClose => spin_sleep::sleep(sleep),
+            _ => unreachable!("rejected by Server::serve_tls"),
+        }
+        log::info!("implicit drop & close of the connection");
+        drop(conn);
+        Ok(())
+    }
+
+    /// the Unix domain socket counterpart of `handle_conn`
+    fn handle_conn_unix(
+        conn: UnixStream,
+        (mode, sleep, framing, compress, endian, verify_sequence): (
+            TeardownMode,
+            std::time::Duration,
+            (Framing, WordSize),
+            Compress,
+            Endian,
+            bool,
+        ),
+    ) -> Result<(), failure::Error> {
+        let mut reader = compress.reader(&conn);
+        let (odd, evens, in_sequence) =
+            Self::read_until_odd(&mut reader, framing, endian, verify_sequence)?;
+        drop(reader);
+        log::info!(
+            "client sent odd number {:?} after {:?} even numbers",
+            odd,
+            evens
+        );
+        let response = Self::encode_response(endian, odd, verify_sequence, in_sequence, mode);
+        (&conn)
+            .write_all(&response)
+            .context("write odd number to connection")?;
+
+        match mode {
+            TeardownMode::CloseImmediately => {}
+            TeardownMode::SleepThenClose => spin_sleep::sleep(sleep),
+            TeardownMode::DrainThenClose => {
+                let drained = Self::drain_from(&conn)?;
+                log::info!("drained {} bytes", drained);
+            }
+            TeardownMode::ShutdownWriteThenDrain => {
+                conn.shutdown(net::Shutdown::Write).context("shutdown")?;
+                let drained = Self::drain_from(&conn)?;
+                log::info!("drained {} bytes after shutdown", drained);
+            }
+            TeardownMode::Shutdown { half } => {
+                conn.shutdown(half.into())
+                    .with_context(|_| format!("shutdown {}", half))?;
+            }
+            TeardownMode::DoubleShutdownWrite => {
+                conn.shutdown(net::Shutdown::Write)
+                    .context("first shutdown write")?;
+                let second = conn.shutdown(net::Shutdown::Write);
+                log::info!("second shutdown write: {:?}", second);
+            }
+            _ => unreachable!("rejected by Server::serve_unix"),
+        }
+        log::info!("implicit drop & close of the connection");
+        Ok(())
+    }
+
+    /// the response echoing `odd`, with `--verify-sequence` followed by the proof of receipt
+    fn encode_response(
+        endian: Endian,
+        odd: u32,
+        verify_sequence: bool,
+        in_sequence: u64,
+        mode: TeardownMode,
+    ) -> Vec<u8> {
+        let mut buf = [0_u8; 12];
+        endian.write_u32(&mut buf[..4], odd);
+        if !verify_sequence {
+            return buf[..4].to_vec();
+        }
+        let in_sequence = in_sequence.min(u64::from(u32::MAX)) as u32;
+        endian.write_u32(&mut buf[4..8], in_sequence);
+        endian.write_u32(&mut buf[8..], mode.code());
+        buf.to_vec()
+    }
+
+    /// the tokio counterpart of `handle_conn`, the final drop happens in the task
+    #[cfg(feature = "tokio")]
+    async fn handle_conn_tokio(
+        conn: tokio::net::TcpStream,
+        mode: TeardownMode,
+        sleep: std::time::Duration,
+        linger: Option<std::time::Duration>,
+        endian: Endian,
+    ) -> Result<(), failure::Error> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut conn, ()) =
+            with_std_conn(conn, |conn| socket2::SockRef::from(conn).set_linger(linger))
+                .context("set linger")?;
+        // buffered like the std backend, to avoid a syscall for every number
+        let mut reader = tokio::io::BufReader::new(&mut conn);
+        let mut evens = 0;
+        let odd = loop {
+            let mut buf = [0_u8; 4];
+            reader
+                .read_exact(&mut buf)
+                .await
+                .context("read from connection")?;
+            let num = endian.read_u32(&buf);
+            if num % 2 == 1 {
+                break num;
+            }
+            evens += 1;
+        };
+        drop(reader);
+        log::info!(
+            "client sent odd number {:?} after {:?} even numbers",
+            odd,
+            evens
+        );
+        let mut buf = [0_u8; 4];
+        endian.write_u32(&mut buf, odd);
+        conn.write_all(&buf)
+            .await
+            .context("write odd number to connection")?;
+        conn.flush().await.context("flush response")?;
+
+        let drain = |mut conn: tokio::net::TcpStream| async move {
+            let mut buf = vec![0_u8; 1 << 15];
+            let mut bytecount = 0_u64;
+            loop {
+                match conn.read(&mut buf).await.context("read from connection")? {
+                    0 => return Ok::<_, failure::Error>((conn, bytecount)),
+                    n => bytecount += n as u64,
+                }
+            }
+        };
+        let linger_zero = |conn| {
+            with_std_conn(conn, |conn| {
+                socket2::SockRef::from(conn).set_linger(Some(std::time::Duration::from_secs(0)))
+            })
+        };
+        let conn = match mode {
+            TeardownMode::CloseImmediately => conn,
+            TeardownMode::ResetImmediately => linger_zero(conn).context("set linger")?.0,
+            TeardownMode::DrainThenClose => {
+                let (conn, drained) = drain(conn).await?;
+                log::info!("drained {} bytes", drained);
+                conn
+            }
+            TeardownMode::ShutdownWriteThenDrain => {
+                conn.shutdown().await.context("shutdown")?;
+                let (conn, drained) = drain(conn).await?;
+                log::info!("drained {} bytes after shutdown", drained);
+                conn
+            }
+            TeardownMode::Shutdown { half } => {
+                with_std_conn(conn, |conn| conn.shutdown(half.into()))
+                    .with_context(|_| format!("shutdown {}", half))?
+                    .0
+            }
+            TeardownMode::SleepThenClose => {
+                tokio::time::sleep(sleep).await;
+                conn
+            }
+            TeardownMode::ShutdownWriteWaitFinThenReset => {
+                conn.shutdown().await.context("shutdown")?;
+                let (conn, drained) = drain(conn).await?;
+                log::info!("received peer FIN after draining {} bytes", drained);
+                linger_zero(conn).context("set linger")?.0
+            }
+            TeardownMode::LingerZeroThenShutdown => {
+                let (conn, ()) = linger_zero(conn).context("set linger")?;
+                with_std_conn(conn, |conn| conn.shutdown(net::Shutdown::Both))
+                    .context("shutdown")?
+                    .0
+            }
+            TeardownMode::DoubleShutdownWrite => {
+                let (conn, second) = with_std_conn(conn, |conn| {
+                    conn.shutdown(net::Shutdown::Write)?;
+                    Ok(conn.shutdown(net::Shutdown::Write))
+                })
+                .context("first shutdown write")?;
+                log::info!("second shutdown write: {:?}", second);
+                conn
+            }
+            TeardownMode::CloseAfterNEvenNumbers | TeardownMode::FloodThenClose => {
+                unreachable!("rejected by Server::serve_tokio")
+            }
+        };
+        log::info!("implicit drop & close of the connection");
+        drop(conn);
+        Ok(())
+    }
+
+    /// set up an accepted connection, handle it, and count its outcome in `stats`
+    fn serve_conn(
+        &self,
+        conn: TcpStream,
+        accepted_at: std::time::Instant,
+        events: &EventLog,
+        reports: &ReportLog,
+        rng: &Mutex<rand::rngs::StdRng>,
+        stats: &mut ServerStats,
+    ) {
+        let accept_to_handle = accepted_at.elapsed();
+        let _span = ConnLogSpan::enter(conn.peer_addr(), conn.local_addr());
+        log::debug!("accept to handle: {:?}", accept_to_handle);
+        stats.record_accept_to_handle(accept_to_handle);
+        let conn_events = events.conn(&conn);
+        let label = conn_label(conn.peer_addr(), conn.local_addr());
+        let mut report = ConnReport {
+            accept_to_handle: Some(accept_to_handle),
+            ..ConnReport::default()
+        };
+        let (local, peer) = (conn.local_addr().ok(), conn.peer_addr().ok());
+        let res = self.configure_conn(&conn).and_then(|()| {
+            if self.dump_sockopts {
+                dump_sockopts(&conn);
+            }
+            if let Some(cmd) = &self.exec {
+                match Self::exec_handler(cmd, conn) {
+                    Ok(()) => {
+                        conn_events.record("exec");
+                        stats.handed_off += 1;
+                    }
+                    Err(e) => log::error!("cannot hand connection to {:?}: {:?}", cmd, e),
+                }
+                return Ok(None);
+            }
+            let res = self.handle_conn(conn, &conn_events, rng, &mut report);
+            capture::log_timeline(local, peer);
+            res.map(Some)
+        });
+        // a failing connection is counted like any other outcome, the server
+        // keeps accepting
+        let outcome = match res {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("connection failed: {:?}", e);
+                ConnOutcome::failed(&e)
+            }
+        };
+        reports.record(&label, &report, &outcome);
+        stats.record_queues_at_teardown(&report.timings);
+        match outcome {
+            ConnOutcome::Completed { evens_before_odd } => {
+                if let Some(evens) = evens_before_odd {
+                    stats.record_evens_before_odd(evens);
+                }
+            }
+            ConnOutcome::ProtocolError => stats.protocol_errors += 1,
+            ConnOutcome::ClientGoneBeforeEcho => stats.client_gone_before_echo += 1,
+            ConnOutcome::LifetimeExpired => stats.lifetime_expired += 1,
+            ConnOutcome::OddWaitExpired => stats.odd_wait_expired += 1,
+            ConnOutcome::TeardownError(_) => stats.teardown_errors += 1,
+        }
+    }
+
+    /// apply `--linger` and `--nonblocking` to an accepted connection
+    fn configure_conn(&self, conn: &TcpStream) -> Result<(), failure::Error> {
+        socket2::SockRef::from(conn)
+            .set_linger(self.linger.map(|hd| hd.into()))
+            .context("set linger")?;
+        if self.nonblocking {
+            conn.set_nonblocking(true).context("set nonblocking")?;
+        }
+        Ok(())
+    }
+
+    /// run `cmd` with `conn` as its stdin and stdout, closing our handle
+    ///
+    /// The child is reaped on a background thread, so the server can accept the
+    /// next connection right away.
+    #[cfg(unix)]
+    fn exec_handler(cmd: &str, conn: TcpStream) -> Result<(), failure::Error> {
+        use std::os::unix::io::OwnedFd;
+        use std::process::{Command, Stdio};
+
+        let (client, server) = (conn.peer_addr(), conn.local_addr());
+        let fd = OwnedFd::from(conn);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::from(fd.try_clone().context("dup connection fd")?))
+            .stdout(Stdio::from(fd))
+            .spawn()
+            .context("spawn")?;
+        log::info!("handed connection to {:?} (pid {})", cmd, child.id());
+        std::thread::spawn(move || {
+            let _span = ConnLogSpan::enter(client, server);
+            match child.wait() {
+                Ok(status) => log::info!("exec handler exited: {}", status),
+                Err(e) => log::error!("cannot wait for exec handler: {:?}", e),
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn exec_handler(_cmd: &str, _conn: TcpStream) -> Result<(), failure::Error> {
+        failure::bail!("--exec is Unix only")
+    }
+
+    fn handle_conn(
+        &self,
+        mut conn: TcpStream,
+        events: &ConnEvents,
+        rng: &Mutex<rand::rngs::StdRng>,
+        report: &mut ConnReport,
+    ) -> Result<ConnOutcome, failure::Error> {
+        let timings = &mut report.timings;
+
+        let (teardown_mode, sleep) = {
+            let mut rng = rng.lock().unwrap();
+            let mode = match (&self.mode_weights, self.teardown_mode) {
+                (Some(weights), _) => {
+                    let mode = weights.sample(&mut *rng);
+                    log::info!("picked teardown mode {}", mode);
+                    mode
+                }
+                (None, ServerMode::Random) => {
+                    let modes = TeardownMode::all().collect::<Vec<_>>();
+                    let mode = modes[rng.random_range(0..modes.len())];
+                    log::info!("picked teardown mode {}", mode);
+                    mode
+                }
+                (None, ServerMode::Fixed(mode)) => mode,
+            };
+            let sleep = match &self.sleep_dist {
+                Some(dist) => {
+                    let sleep = dist.sample(&mut *rng);
+                    log::info!("sampled sleep {:?}", sleep);
+                    sleep
+                }
+                None => self.sleep.into(),
+            };
+            (mode, sleep)
+        };
+        report.mode = Some(teardown_mode);
+        let lifetime = match self.max_lifetime {
+            Some(lifetime) => Some(
+                LifetimeWatcher::start(&conn, lifetime.into()).context("start lifetime watcher")?,
+            ),
+            None => None,
+        };
+        let expired = || lifetime.as_ref().is_some_and(LifetimeWatcher::expired);
+
+        let mut evens_before_odd = None;
+        let (mode, response_written) = match teardown_mode {
+            mode if matches!(self.workload, Workload::Echo) => {
+                // there are no numbers to count, the echo trigger takes their place
+                let mode = match mode {
+                    TeardownMode::CloseAfterNEvenNumbers => self.close_after_mode,
+                    mode => mode,
+                };
+                match self.echo(&conn) {
+                    Ok(echoed) => log::info!("echoed {} bytes, tearing down with {}", echoed, mode),
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::BrokenPipe
+                            || e.kind() == io::ErrorKind::ConnectionReset =>
+                    {
+                        log::warn!("client gone while echoing, dropping connection: {:?}", e);
+                        return Ok(ConnOutcome::ClientGoneBeforeEcho);
+                    }
+                    Err(e) => return Err(e).context("echo")?,
+                }
+                events.record("echo-done");
+                (mode, Some(std::time::Instant::now()))
+            }
+            TeardownMode::CloseAfterNEvenNumbers => {
+                // tear down in the middle of the request stream, without response
+                let mut reader = self.compress.reader(SpinOnWouldBlock(&conn));
+                if let Err(e) = Self::read_n(
+                    &mut reader,
+                    (self.framing, self.word_size),
+                    self.endian,
+                    self.close_after,
+                ) {
+                    if expired() {
+                        drop(reader);
+                        return self.expire(
+                            Expiry::Lifetime,
+                            self.close_after_mode,
+                            conn,
+                            timings,
+                            events,
+                            sleep,
+                        );
+                    }
+                    log::warn!("protocol error, dropping connection: {:?}", e);
+                    return Ok(ConnOutcome::ProtocolError);
+                }
+                log::info!(
+                    "read {:?} numbers, tearing down without response",
+                    self.close_after
+                );
+                (self.close_after_mode, None)
+            }
+            mode => {
+                // read from the connection until we encounter the first odd number
+                let odd_deadline = self
+                    .max_odd_wait
+                    .map(|wait| std::time::Instant::now() + *wait);
+                let mut reader = match odd_deadline {
+                    Some(deadline) => self.compress.reader(ReadDeadline {
+                        conn: &conn,
+                        deadline,
+                    }),
+                    None => self.compress.reader(SpinOnWouldBlock(&conn)),
+                };
+                let res = time_and_log_debug!(
+                    "read until odd duration",
+                    timings.read_until_odd,
+                    Self::read_until_odd(
+                        &mut reader,
+                        (self.framing, self.word_size),
+                        self.endian,
+                        self.verify_sequence
+                    )
+                );
+                drop(reader);
+                if odd_deadline.is_some() {
+                    conn.set_read_timeout(None).context("clear read timeout")?;
+                }
+                let (first_odd_num, evens, in_sequence) = match res {
+                    Ok(res) => res,
+                    Err(_) if expired() => {
+                        return self.expire(Expiry::Lifetime, mode, conn, timings, events, sleep);
+                    }
+                    Err(_) if odd_deadline.is_some_and(|d| std::time::Instant::now() >= d) => {
+                        log::info!("no odd number within budget");
+                        return self.expire(
+                            Expiry::OddWait,
+                            self.odd_wait_mode,
+                            conn,
+                            timings,
+                            events,
+                            sleep,
+                        );
+                    }
+                    Err(e) => {
+                        log::warn!("protocol error, dropping connection: {:?}", e);
+                        return Ok(ConnOutcome::ProtocolError);
+                    }
+                };
+                evens_before_odd = Some(evens);
+                events.record("first-odd-read");
+
+                // send the odd number back to the client
+                //
+                // The whole response must be handed to the kernel before any
+                // teardown, otherwise the FIN or RST could overtake it.
+                if self.verify_sequence {
+                    log::info!(
+                        "proving receipt of {} numbers in sequence, teardown mode {}",
+                        in_sequence,
+                        mode
+                    );
+                }
+                let buf = Self::encode_response(
+                    self.endian,
+                    first_odd_num,
+                    self.verify_sequence,
+                    in_sequence,
+                    mode,
+                );
+                if self.fragment_echo {
+                    conn.set_nodelay(true).context("set nodelay")?;
+                }
+                let write_res =
+                    time_and_log_debug!("write response duration", timings.write_response, {
+                        let mut echo = SpinOnWouldBlock(&conn);
+                        if self.fragment_echo {
+                            buf.chunks(1)
+                                .try_for_each(|byte| echo.write_all(byte))
+                                .and_then(|()| echo.flush())
+                        } else {
+                            echo.write_all(&buf).and_then(|()| echo.flush())
+                        }
+                    });
+                match write_res {
+                    Ok(_) => {}
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::BrokenPipe
+                            || e.kind() == io::ErrorKind::ConnectionReset =>
+                    {
+                        log::warn!("client gone before echo, dropping connection: {:?}", e);
+                        return Ok(ConnOutcome::ClientGoneBeforeEcho);
+                    }
+                    Err(e) => {
+                        log::error!("unexpected error writing echo: {:?}", e);
+                        return Err(e).context("write odd number to connection")?;
+                    }
+                }
+                events.record("echo-write");
+                (mode, Some(std::time::Instant::now()))
+            }
+        };
+
+        debug_assert!(
+            response_written.is_some()
+                || matches!(teardown_mode, TeardownMode::CloseAfterNEvenNumbers),
+            "teardown before the response was written"
+        );
+        if self.proc_stats {
+            log_proc_tcp_stats(&conn);
+        }
+        if self.tcp_info {
+            log_tcp_info(&conn, "before teardown");
+        }
+        self.teardown(mode, &mut conn, timings, events, response_written, sleep)?;
+        if self.tcp_info {
+            log_tcp_info(&conn, "after teardown");
+        }
+        time_and_log_debug!("close duration", timings.close, {
+            close_conn(conn, self.io_uring);
+        });
+        events.record("close");
+
+        log::info!("connection timings: {:?}", timings);
+        report.evens_before_odd = evens_before_odd;
+        Ok(ConnOutcome::Completed { evens_before_odd })
+    }
+
+    /// send back what the client sends until `--echo-bytes` were echoed,
+    /// `--echo-duration` passed or the client shut down its sending side
+    fn echo(&self, conn: &TcpStream) -> io::Result<u64> {
+        let deadline = self
+            .echo_duration
+            .map(|duration| std::time::Instant::now() + *duration);
+        let limit = self.echo_bytes.unwrap_or(u64::MAX);
+        let mut buf = vec![0_u8; 1 << 15];
+        let mut echoed = 0_u64;
+        while echoed < limit {
+            if let Some(deadline) = deadline {
+                match deadline.checked_duration_since(std::time::Instant::now()) {
+                    Some(left) if !left.is_zero() => conn.set_read_timeout(Some(left))?,
+                    _ => break,
+                }
+            }
+            let want = (buf.len() as u64).min(limit - echoed) as usize;
+            let n = match (&*conn).read(&mut buf[..want]) {
+                Ok(0) => {
+                    log::info!("client shut down its sending side while echoing");
+                    break;
+                }
+                Ok(n) => n,
+                // the deadline check above ends the loop
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::Interrupted =>
+                {
+                    if deadline.is_none() {
+                        std::thread::yield_now();
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            SpinOnWouldBlock(conn).write_all(&buf[..n])?;
+            echoed += n as u64;
+        }
+        if deadline.is_some() {
+            conn.set_read_timeout(None)?;
+        }
+        Ok(echoed)
+    }
+
+    /// tear down a connection whose lifetime or odd number wait expired before
+    /// the odd number was read
+    fn expire(
+        &self,
+        expiry: Expiry,
+        mode: TeardownMode,
+        mut conn: TcpStream,
+        timings: &mut ConnTimings,
+        events: &ConnEvents,
+        sleep: std::time::Duration,
+    ) -> Result<ConnOutcome, failure::Error> {
+        let (event, what, outcome) = match expiry {
+            Expiry::Lifetime => ("lifetime-expired", "lifetime", ConnOutcome::LifetimeExpired),
+            Expiry::OddWait => (
+                "odd-wait-expired",
+                "odd number wait",
+                ConnOutcome::OddWaitExpired,
+            ),
+        };
+        events.record(event);
+        log::info!(
+            "{} expired before the odd number, tearing down with {}",
+            what,
+            mode
+        );
+        self.teardown(mode, &mut conn, timings, events, None, sleep)?;
+        time_and_log_debug!("close duration", timings.close, {
+            close_conn(conn, self.io_uring);
+        });
+        events.record("close");
+        log::info!("connection timings: {:?}", timings);
+        Ok(outcome)
+    }
+
+    /// perform the teardown `mode` on `conn`, except for the final drop
+    fn teardown(
+        &self,
+        mode: TeardownMode,
+        conn: &mut TcpStream,
+        timings: &mut ConnTimings,
+        events: &ConnEvents,
+        response_written: Option<std::time::Instant>,
+        sleep: std::time::Duration,
+    ) -> Result<(), failure::Error> {
+        match socket_queues(conn) {
+            Ok((unsent, unread)) => {
+                log::info!(
+                    "before teardown: {} bytes unacknowledged by the client, {} bytes unread",
+                    unsent,
+                    unread
+                );
+                timings.unsent_at_teardown = Some(unsent);
+                timings.unread_at_teardown = Some(unread);
+            }
+            Err(e) => log::debug!("cannot get socket queue sizes: {:?}", e),
+        }
+        match mode {
+            TeardownMode::CloseImmediately => {}
+            TeardownMode::ResetImmediately => {
+                log::info!("setting linger to zero");
+                socket2::SockRef::from(&*conn)
+                    .set_linger(Some(std::time::Duration::from_secs(0)))
+                    .context("set linger")?;
+                events.record("linger-zero");
+                log::info!("implicit drop & reset of the connection");
+            }
+            TeardownMode::SleepThenClose => {
+                spin_sleep::sleep(sleep);
+            }
+
+            TeardownMode::DrainThenClose => {
+                log::info!("draining connection");
+                let (drained_bytes, end) =
+                    time_and_log_debug!("drain duration", timings.drain, self.drain_conn(conn)?);
+                timings.drained_bytes = Some(drained_bytes);
+                events.record(end.event());
+                timings.response_to_eof = response_written.map(|t| t.elapsed());
+                log::info!(
+                    "drained {:?} bytes, {} {:?} after response",
+                    drained_bytes,
+                    end,
+                    timings.response_to_eof
+                );
+
+                log::info!("implicit drop & close of the connection");
+            }
+            TeardownMode::ShutdownWriteThenDrain => {
+                log::info!("shutting down write-end of the connection");
+                conn.shutdown(net::Shutdown::Write).context("shutdown")?;
+                events.record("shutdown-write");
+                let shut_down = std::time::Instant::now();
+
+                log::info!("draining connection");
+                let (drained_bytes, end) = self.drain_conn(conn)?;
+                timings.drained_bytes = Some(drained_bytes);
+                events.record(end.event());
+                timings.shutdown_to_eof = Some(shut_down.elapsed());
+                log::info!(
+                    "drained {:?} bytes, {} {:?} after shutdown",
+                    drained_bytes,
+                    end,
+                    timings.shutdown_to_eof
+                );
+
+                log::info!("implicit drop & close of the connection");
+            }
+
+            TeardownMode::Shutdown { half } => {
+                if let (ShutdownHalf::Both, true) = (half, self.detect_simultaneous) {
+                    match self.peer_shut_down(conn) {
+                        Ok(true) => {
+                            events.record("peer-fin-before-shutdown");
+                            log::info!("peer FIN already received, simultaneous close likely");
+                        }
+                        Ok(false) => log::debug!("no peer FIN before shutdown"),
+                        Err(e) => log::warn!("cannot peek for peer FIN: {:?}", e),
+                    }
+                }
+                time_and_log_debug!(format!("shutdown {} duration", half), {
+                    conn.shutdown(half.into())
+                        .with_context(|_| format!("shutdown {}", half))?;
+                    events.record(&format!("shutdown-{}", half));
+                });
+            }
+
+            TeardownMode::ShutdownWriteWaitFinThenReset => {
+                // our FIN goes out first, then we wait for the peer's FIN,
+                // and only then reset the connection
+                let start = std::time::Instant::now();
+                log::info!(
+                    "t+{:?}: shutting down write-end of the connection",
+                    start.elapsed()
+                );
+                conn.shutdown(net::Shutdown::Write).context("shutdown")?;
+                events.record("shutdown-write");
+
+                log::info!("t+{:?}: waiting for peer FIN", start.elapsed());
+                let (drained_bytes, end) = self.drain_conn(conn)?;
+                timings.drained_bytes = Some(drained_bytes);
+                events.record(end.event());
+                log::info!(
+                    "t+{:?}: stopped draining at {} after {:?} bytes",
+                    start.elapsed(),
+                    end,
+                    drained_bytes
+                );
+
+                log::info!("t+{:?}: setting linger to zero for reset", start.elapsed());
+                socket2::SockRef::from(&*conn)
+                    .set_linger(Some(std::time::Duration::from_secs(0)))
+                    .context("set linger")?;
+
+                log::info!(
+                    "t+{:?}: implicit drop & reset of the connection",
+                    start.elapsed()
+                );
+            }
+
+            TeardownMode::FloodThenClose => {
+                log::info!("flooding connection with {} bytes", self.flood_bytes);
+                let (written, res) = Self::flood(conn, self.flood_bytes, self.write_timeout.into());
+                events.record("flood-end");
+                match res {
+                    Ok(()) => log::info!("flooded {} bytes", written),
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        log::warn!("flood timed out after {} bytes", written)
+                    }
+                    Err(e) => {
+                        // the connection is broken, the sub-mode has nothing to tear down
+                        log::warn!("flood stopped after {} bytes: {:?}", written, e);
+                        return Ok(());
+                    }
+                }
+                self.teardown(
+                    self.flood_mode,
+                    conn,
+                    timings,
+                    events,
+                    response_written,
+                    sleep,
+                )?;
+            }
+
+            TeardownMode::LingerZeroThenShutdown => {
+                log::info!("setting linger to zero");
+                socket2::SockRef::from(&*conn)
+                    .set_linger(Some(std::time::Duration::from_secs(0)))
+                    .context("set linger")?;
+                time_and_log_debug!("shutdown duration", {
+                    conn.shutdown(net::Shutdown::Both).context("shutdown")?;
+                    events.record("shutdown-both");
+                });
+                log::info!("implicit drop & reset of the connection");
+            }
+
+            TeardownMode::DoubleShutdownWrite => {
+                let first = conn.shutdown(net::Shutdown::Write);
+                events.record("shutdown-write");
+                log::info!("first shutdown write: {:?}", first);
+                first.context("first shutdown write")?;
+                match conn.shutdown(net::Shutdown::Write) {
+                    Ok(()) => log::info!("second shutdown write: Ok, a no-op"),
+                    Err(e) => log::info!("second shutdown write: error {:?}: {}", e.kind(), e),
+                }
+                events.record("shutdown-write");
+            }
+
+            TeardownMode::CloseAfterNEvenNumbers => {
+                unreachable!("rejected as --close-after-mode by Server::serve")
+            }
+        }
+        Ok(())
+    }
+
+    /// whether the peer's FIN is already queued on `conn`, without consuming any data
+    ///
+    /// Unread data ahead of the FIN hides it, so this can report false negatives.
+    fn peer_shut_down(&self, conn: &TcpStream) -> io::Result<bool> {
+        conn.set_nonblocking(true)?;
+        let res = match conn.peek(&mut [0_u8; 1]) {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        conn.set_nonblocking(self.nonblocking)?;
+        res
+    }
+
+    /// write `n` bytes to the connection, giving up after `timeout`
+    ///
+    /// Returns the number of bytes written, and why the flood stopped early.
+    fn flood(conn: &TcpStream, n: u64, timeout: std::time::Duration) -> (u64, io::Result<()>) {
+        let deadline = std::time::Instant::now() + timeout;
+        if let Err(e) = conn.set_write_timeout(Some(timeout)) {
+            return (0, Err(e));
+        }
+        let buf = vec![0xAA_u8; 1 << 16];
+        let mut written = 0;
+        while written < n {
+            let len = std::cmp::min(buf.len() as u64, n - written) as usize;
+            match (&*conn).write(&buf[..len]) {
+                Ok(n) => written += n as u64,
+                // nonblocking sockets don't observe the write timeout, spin until the deadline
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        && std::time::Instant::now() < deadline =>
+                {
+                    std::thread::yield_now()
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (written, Err(e)),
+            }
+        }
+        (written, Ok(()))
+    }
+
+    /// read `n` numbers from the connection, regardless of their parity
+    fn read_n<R: Read>(
+        conn: &mut R,
+        (framing, word_size): (Framing, WordSize),
+        endian: Endian,
+        n: u64,
+    ) -> Result<(), failure::Error> {
+        for _ in 0..n {
+            framing
+                .read_message(conn, endian, word_size)
+                .context("read from connection")?;
+        }
+        Ok(())
+    }
+
+    /// read numbers from the connection until the first odd one, which is returned
+    ///
+    /// Also returns how many even numbers preceded the odd one, and how many
+    /// numbers from the start, the odd one included, followed the
+    /// `--verify-sequence` stream, where the `i`-th number is `2i` or `2i + 1`.
+    fn read_until_odd<R: Read>(
+        conn: &mut R,
+        (framing, word_size): (Framing, WordSize),
+        endian: Endian,
+        verify_sequence: bool,
+    ) -> Result<(u32, u64, u64), failure::Error> {
+        let mut evens = 0;
+        let mut in_sequence = 0;
+        loop {
+            let num = framing
+                .read_message(conn, endian, word_size)
+                .context("read from connection")?;
+
+            let index = evens;
+            if in_sequence == index {
+                if u64::from(num / 2) == index {
+                    in_sequence += 1;
+                } else if verify_sequence {
+                    log::warn!("sequence broken at number #{}: {}", index, num);
+                }
+            }
+            if num % 2 == 0 {
+                evens += 1;
+                continue;
+            } else {
+                log::info!(
+                    "client sent odd number {:?} after {:?} even numbers",
+                    num,
+                    evens
+                );
+                return Ok((num, evens, in_sequence));
+            }
+        }
+    }
+
+    /// read & discard from the connection until EOF
+    pub fn drain(conn: &mut TcpStream) -> Result<u64, failure::Error> {
+        Self::drain_from(SpinOnWouldBlock(&*conn))
+    }
+
+    /// `drain`, with the reads submitted through io_uring with `--io-uring`, and
+    /// giving up early at `--drain-limit` or `--drain-timeout`
+    fn drain_conn(&self, conn: &mut TcpStream) -> Result<(u64, DrainEnd), failure::Error> {
+        #[cfg(feature = "io-uring")]
+        {
+            if self.io_uring {
+                let ring_conn = uring::Conn::new(&*conn).context("set up io_uring")?;
+                return Ok((
+                    Self::drain_from(SpinOnWouldBlock(ring_conn))?,
+                    DrainEnd::Eof,
+                ));
+            }
+        }
+        if self.drain_limit.is_none() && self.drain_timeout.is_none() {
+            return Ok((Self::drain(conn)?, DrainEnd::Eof));
+        }
+
+        let limit = self.drain_limit.unwrap_or(u64::MAX);
+        let deadline = self
+            .drain_timeout
+            .map(|timeout| std::time::Instant::now() + *timeout);
+        let mut bytecount = 0;
+        let mut buf = vec![0_u8; 1 << 15];
+        let end = loop {
+            if bytecount >= limit {
+                break DrainEnd::Limit;
+            }
+            if let Some(deadline) = deadline {
+                match deadline.checked_duration_since(std::time::Instant::now()) {
+                    Some(left) if left > std::time::Duration::from_secs(0) => conn
+                        .set_read_timeout(Some(left))
+                        .context("set read timeout")?,
+                    _ => break DrainEnd::Timeout,
+                }
+            }
+            let len = buf.len().min((limit - bytecount) as usize);
+            match conn.read(&mut buf[..len]) {
+                Ok(0) => break DrainEnd::Eof,
+                Ok(n) => bytecount += n as u64,
+                // nonblocking sockets spin, blocking ones hit the read timeout
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::debug!("error while draining: {:?}", e);
+                    return Err(e).context("read from connection")?;
+                }
+            }
+        };
+        if let DrainEnd::Limit | DrainEnd::Timeout = end {
+            log::info!(
+                "gave up draining at {} after {} bytes, leaving the rest queued",
+                end,
+                bytecount
+            );
+        }
+        Ok((bytecount, end))
+    }
+
+    fn drain_from(mut conn: impl Read) -> Result<u64, failure::Error> {
+        let mut bytecount = 0;
+        let mut buf = vec![0_u8; 1 << 15];
+        loop {
+            match conn.read(&mut buf) {
+                Ok(0) => return Ok(bytecount),
+                Ok(n) => bytecount += n as u64,
+                Err(e) => {
+                    log::debug!("error while draining: {:?}", e);
+                    return Err(e).context("read from connection")?;
+                }
+            }
+        }
+    }
+}
+
+/// Why draining a connection stopped.
+#[derive(Clone, Copy, Display)]
+enum DrainEnd {
+    #[strum(serialize = "EOF")]
+    Eof,
+    #[strum(serialize = "--drain-limit")]
+    Limit,
+    #[strum(serialize = "--drain-timeout")]
+    Timeout,
+}
+
+impl DrainEnd {
+    /// name for `--events-out`
+    fn event(self) -> &'static str {
+        match self {
+            DrainEnd::Eof => "drain-eof",
+            DrainEnd::Limit => "drain-limit",
+            DrainEnd::Timeout => "drain-timeout",
+        }
+    }
+}
+
+/// Reads from a connection until `deadline`, then fails with `TimedOut`.
+///
+/// Blocking sockets wait in the kernel via the read timeout, nonblocking ones
+/// spin on `WouldBlock` like `SpinOnWouldBlock`.
+struct ReadDeadline<'a> {
+    conn: &'a TcpStream,
+    deadline: std::time::Instant,
+}
+
+impl Read for ReadDeadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let remaining = self
+                .deadline
+                .checked_duration_since(std::time::Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "deadline expired"))?;
+            self.conn.set_read_timeout(Some(remaining))?;
+            match self.conn.read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Server {
+    /// the server as the command line configures it from just the listen
+    /// address and the teardown mode
+    pub fn defaults(listen: net::SocketAddr, mode: TeardownMode) -> Server {
+        Server::from_iter_safe(vec![
+            "server".to_string(),
+            listen.to_string(),
+            mode.to_string(),
+        ])
+        .expect("the server's defaults parse")
+    }
+}
+
+/// Configures and starts an in-process server.
+///
+/// Options not set here take the command line's defaults.
+pub struct ServerBuilder {
+    server: Server,
+}
+
+impl ServerBuilder {
+    /// a server on an ephemeral loopback port, tearing connections down with `mode`
+    pub fn new(mode: TeardownMode) -> Self {
+        let mut server = Server::defaults(([127, 0, 0, 1], 0).into(), mode);
+        server.shutdown_grace = Some(std::time::Duration::from_secs(10).into());
+        ServerBuilder { server }
+    }
+
+    pub fn listen(mut self, addr: net::SocketAddr) -> Self {
+        self.server.listen = addr.to_string();
+        self
+    }
+
+    /// pick the teardown mode per connection instead, uniformly
+    pub fn random_mode(mut self) -> Self {
+        self.server.teardown_mode = ServerMode::Random;
+        self
+    }
+
+    /// seed for per-connection random choices, for reproducible experiments
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.server.seed = Some(seed);
+        self
+    }
+
+    /// time to sleep for teardown modes that sleep
+    pub fn sleep(mut self, sleep: std::time::Duration) -> Self {
+        self.server.sleep = sleep.into();
+        self
+    }
+
+    /// SO_LINGER of accepted connections, `None` to disable lingering
+    pub fn linger(mut self, linger: Option<std::time::Duration>) -> Self {
+        self.server.linger = linger.map(Into::into);
+        self
+    }
+
+    /// draining teardown modes give up after reading this many bytes
+    pub fn drain_limit(mut self, bytes: u64) -> Self {
+        self.server.drain_limit = Some(bytes);
+        self
+    }
+
+    /// draining teardown modes give up if EOF does not arrive within this time
+    pub fn drain_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.server.drain_timeout = Some(timeout.into());
+        self
+    }
+
+    /// number of even numbers `CloseAfterNEvenNumbers` reads, and the teardown it performs then
+    pub fn close_after(mut self, evens: u64, mode: TeardownMode) -> Self {
+        self.server.close_after = evens;
+        self.server.close_after_mode = mode;
+        self
+    }
+
+    /// number of bytes `FloodThenClose` writes, and the teardown it performs then
+    pub fn flood(mut self, bytes: u64, mode: TeardownMode) -> Self {
+        self.server.flood_bytes = bytes;
+        self.server.flood_mode = mode;
+        self
+    }
+
+    /// echo everything back instead of the odd number protocol, tearing down
+    /// after `bytes` or `duration`, or once the client shuts down its sending side
+    pub fn echo(mut self, bytes: Option<u64>, duration: Option<std::time::Duration>) -> Self {
+        self.server.workload = Workload::Echo;
+        self.server.echo_bytes = bytes;
+        self.server.echo_duration = duration.map(Into::into);
+        self
+    }
+
+    /// how long `RunningServer::stop` waits for connections in flight
+    pub fn shutdown_grace(mut self, grace: std::time::Duration) -> Self {
+        self.server.shutdown_grace = Some(grace.into());
+        self
+    }
+
+    /// length of the listening socket's accept queue
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.server.backlog = backlog;
+        self
+    }
+
+    /// disable Nagle's algorithm on accepted connections
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.server.nodelay = nodelay;
+        self
+    }
+
+    /// SO_SNDBUF and SO_RCVBUF in bytes, `None` for the system default
+    pub fn buffer_sizes(mut self, sndbuf: Option<u32>, rcvbuf: Option<u32>) -> Self {
+        self.server.sndbuf = sndbuf;
+        self.server.rcvbuf = rcvbuf;
+        self
+    }
+
+    /// bind and start accepting connections on background threads
+    pub fn spawn(self) -> Result<RunningServer, failure::Error> {
+        let server = self.server;
+        let addr = server
+            .listen
+            .parse::<net::SocketAddr>()
+            .context("parse listen address")?;
+        let listener = server.bind(addr)?;
+        let addr = listener.local_addr().context("listener address")?;
+        log::info!("in-process server listening on {:?}", addr);
+        let (interrupt, interrupt_rx) = crossbeam_channel::bounded(1);
+        let (aborted_tx, aborted) = crossbeam_channel::bounded(1);
+        let interrupt_rx = Interrupt {
+            rx: interrupt_rx,
+            aborted: Some(aborted_tx),
+        };
+        let thread = std::thread::spawn(move || {
+            server.serve(listener, None, &ReportLog::open(None)?, Some(interrupt_rx))
+        });
+        Ok(RunningServer {
+            addr,
+            interrupt,
+            aborted,
+            thread,
+        })
+    }
+}
+
+/// A server started by [`ServerBuilder::spawn`].
+pub struct RunningServer {
+    addr: net::SocketAddr,
+    interrupt: crossbeam_channel::Sender<()>,
+    aborted: crossbeam_channel::Receiver<failure::Error>,
+    thread: std::thread::JoinHandle<Result<ServerStats, failure::Error>>,
+}
+
+impl RunningServer {
+    /// the address to point clients at
+    pub fn local_addr(&self) -> net::SocketAddr {
+        self.addr
+    }
+
+    /// stop accepting and return the stats once the connections in flight are handled
+    ///
+    /// Connections still being handled after the shutdown grace (10s unless set)
+    /// make this return an error naming them. Their threads are left running in
+    /// the background until the connections end.
+    pub fn stop(self) -> Result<ServerStats, failure::Error> {
+        let _ = self.interrupt.send(());
+        // closed without an error once the connections finished within grace
+        if let Ok(e) = self.aborted.recv() {
+            return Err(e);
+        }
+        self.thread.join().expect("server thread panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_weights_parse_and_sample() {
+        let weights: ModeWeights = "close-immediately=3,shutdown:both=1,reset-immediately=0"
+            .parse()
+            .unwrap();
+        let parsed: Vec<_> = weights
+            .0
+            .iter()
+            .map(|(mode, weight)| (mode.to_string(), *weight))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("close-immediately".to_string(), 3),
+                ("shutdown:both".to_string(), 1),
+                ("reset-immediately".to_string(), 0),
+            ]
+        );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            assert!(!matches!(
+                weights.sample(&mut rng),
+                TeardownMode::ResetImmediately
+            ));
+        }
+    }
+
+    #[test]
+    fn mode_weights_reject_malformed_and_out_of_range() {
+        for bad in [
+            "",
+            "close-immediately",
+            "close-immediately=",
+            "no-such-mode=1",
+            "close-immediately=1,",
+            "close-immediately=-1",
+            "close-immediately=4294967296",
+            "close-immediately=0,reset-immediately=0",
+        ] {
+            assert!(bad.parse::<ModeWeights>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn sleep_dist_parse_and_sample() {
+        let ms = std::time::Duration::from_millis;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        let dist: SleepDist = "uniform:10ms:20ms".parse().unwrap();
+        assert!(matches!(dist, SleepDist::Uniform(min, max) if min == ms(10) && max == ms(20)));
+        for _ in 0..1000 {
+            let d = dist.sample(&mut rng);
+            assert!(ms(10) <= d && d <= ms(20), "{:?} out of range", d);
+        }
+        let dist: SleepDist = "uniform:5ms:5ms".parse().unwrap();
+        assert_eq!(dist.sample(&mut rng), ms(5));
+
+        let dist: SleepDist = "exp:1s".parse().unwrap();
+        assert!(matches!(dist, SleepDist::Exp(mean) if mean == ms(1000)));
+    }
+
+    #[test]
+    fn sleep_dist_rejects_malformed_and_out_of_range() {
+        for bad in [
+            "",
+            "uniform",
+            "uniform:10ms",
+            "uniform:10ms:20ms:30ms",
+            "uniform:10:20ms",
+            "uniform:20ms:10ms",
+            "exp",
+            "exp:-1s",
+            "normal:1s",
+        ] {
+            assert!(bad.parse::<SleepDist>().is_err(), "{:?} parsed", bad);
+        }
+    }
+}
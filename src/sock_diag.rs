@@ -0,0 +1,146 @@
+//! TCP sockets as seen by the kernel, through netlink `sock_diag`.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+/// A TCP socket from an `inet_diag` dump.
+pub struct TcpSocket {
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    /// the kernel's `TCP_*` state, see `tcp_state_name`
+    pub state: u8,
+}
+
+/// IPv4-mapped IPv6 addresses, as seen on dual-stack sockets, as IPv4
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// all IPv4 and IPv6 TCP sockets in our network namespace, addresses made `canonical`
+#[cfg(target_os = "linux")]
+pub fn tcp_sockets() -> io::Result<Vec<TcpSocket>> {
+    let mut sockets = dump(libc::AF_INET as u8)?;
+    sockets.extend(dump(libc::AF_INET6 as u8)?);
+    Ok(sockets)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn tcp_sockets() -> io::Result<Vec<TcpSocket>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sock_diag is Linux only",
+    ))
+}
+
+/// `SOCK_DIAG_BY_FAMILY` dump of every TCP socket of `family`
+#[cfg(target_os = "linux")]
+fn dump(family: u8) -> io::Result<Vec<TcpSocket>> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    const SOCK_DIAG_BY_FAMILY: u16 = 20;
+    const NLMSG_HDRLEN: usize = 16;
+    /// `inet_diag_req_v2`, of which only family, protocol and states are set
+    const REQ_LEN: usize = 56;
+    const ALL_STATES: u32 = 0xfff;
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_SOCK_DIAG,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut req = Vec::with_capacity(NLMSG_HDRLEN + REQ_LEN);
+    req.extend_from_slice(&((NLMSG_HDRLEN + REQ_LEN) as u32).to_ne_bytes());
+    req.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    req.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    // sequence number and port id
+    req.extend_from_slice(&[0; 8]);
+    req.extend_from_slice(&[family, libc::IPPROTO_TCP as u8, 0, 0]);
+    req.extend_from_slice(&ALL_STATES.to_ne_bytes());
+    // inet_diag_sockid, no filter
+    req.resize(NLMSG_HDRLEN + REQ_LEN, 0);
+    let sent = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            req.as_ptr() as *const libc::c_void,
+            req.len(),
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut sockets = Vec::new();
+    let mut buf = vec![0_u8; 1 << 16];
+    loop {
+        let len = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        let mut msgs = &buf[..len as usize];
+        while msgs.len() >= NLMSG_HDRLEN {
+            let msg_len = u32::from_ne_bytes([msgs[0], msgs[1], msgs[2], msgs[3]]) as usize;
+            if msg_len < NLMSG_HDRLEN || msg_len > msgs.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated netlink message",
+                ));
+            }
+            let payload = &msgs[NLMSG_HDRLEN..msg_len];
+            match libc::c_int::from(u16::from_ne_bytes([msgs[4], msgs[5]])) {
+                libc::NLMSG_DONE => return Ok(sockets),
+                libc::NLMSG_ERROR => {
+                    let errno =
+                        i32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                    return Err(io::Error::from_raw_os_error(-errno));
+                }
+                _ => sockets.extend(parse(payload)),
+            }
+            // messages are 4-byte aligned
+            msgs = &msgs[((msg_len + 3) & !3).min(msgs.len())..];
+        }
+    }
+}
+
+/// an `inet_diag_msg`
+#[cfg(target_os = "linux")]
+fn parse(msg: &[u8]) -> Option<TcpSocket> {
+    if msg.len() < 72 {
+        return None;
+    }
+    let (family, state) = (msg[0], msg[1]);
+    // inet_diag_sockid: ports, then 16 bytes per address
+    let port = |at: usize| u16::from_be_bytes([msg[at], msg[at + 1]]);
+    let ip = |at: usize| {
+        if libc::c_int::from(family) == libc::AF_INET {
+            IpAddr::from([msg[at], msg[at + 1], msg[at + 2], msg[at + 3]])
+        } else {
+            let mut octets = [0_u8; 16];
+            octets.copy_from_slice(&msg[at..at + 16]);
+            IpAddr::from(octets)
+        }
+    };
+    Some(TcpSocket {
+        local: canonical(SocketAddr::new(ip(8), port(4))),
+        peer: canonical(SocketAddr::new(ip(24), port(6))),
+        state,
+    })
+}
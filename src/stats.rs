@@ -0,0 +1,519 @@
+//! Aggregated results of the server and the client.
+
+use std::collections::HashMap;
+
+use failure::ResultExt;
+
+use crate::client::{PeerTeardown, Phase, RunRecord, SingleRunResult, TlsEof};
+use crate::server::{ConnOutcome, ConnReport, ConnTimings};
+
+/// Counters accumulated over the connections handled by a server.
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    pub accepted: usize,
+    pub protocol_errors: usize,
+    pub client_gone_before_echo: usize,
+    pub lifetime_expired: usize,
+    pub odd_wait_expired: usize,
+    /// connections whose handling failed, e.g. the client reset the
+    /// connection while it was drained
+    pub teardown_errors: usize,
+    /// connections handed to the `--exec` command
+    pub handed_off: usize,
+    /// histogram of the number of even numbers read before the odd one,
+    /// keyed by power-of-two bucket lower bound
+    pub evens_before_odd: std::collections::BTreeMap<u64, usize>,
+    /// histogram of microseconds from `accept` returning to the handler
+    /// picking the connection up, keyed like `evens_before_odd`
+    pub accept_to_handle_us: std::collections::BTreeMap<u64, usize>,
+    /// histogram of the bytes not yet acknowledged by the client when the
+    /// teardown started, keyed like `evens_before_odd`
+    pub unsent_at_teardown: std::collections::BTreeMap<u64, usize>,
+    /// histogram of the bytes received but unread when the teardown started,
+    /// keyed like `evens_before_odd`
+    pub unread_at_teardown: std::collections::BTreeMap<u64, usize>,
+}
+
+/// lower bound of the power-of-two histogram bucket containing `v`
+fn pow2_bucket(v: u64) -> u64 {
+    if v == 0 {
+        0
+    } else {
+        1 << (63 - v.leading_zeros())
+    }
+}
+
+impl ServerStats {
+    pub fn record_evens_before_odd(&mut self, evens: u64) {
+        *self.evens_before_odd.entry(pow2_bucket(evens)).or_insert(0) += 1;
+    }
+
+    pub fn record_accept_to_handle(&mut self, latency: std::time::Duration) {
+        let us = latency.as_micros() as u64;
+        *self.accept_to_handle_us.entry(pow2_bucket(us)).or_insert(0) += 1;
+    }
+
+    pub fn record_queues_at_teardown(&mut self, timings: &ConnTimings) {
+        if let Some(unsent) = timings.unsent_at_teardown {
+            *self
+                .unsent_at_teardown
+                .entry(pow2_bucket(unsent))
+                .or_insert(0) += 1;
+        }
+        if let Some(unread) = timings.unread_at_teardown {
+            *self
+                .unread_at_teardown
+                .entry(pow2_bucket(unread))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// add the counters of `other`, e.g. a worker thread's
+    pub fn merge(&mut self, other: ServerStats) {
+        self.accepted += other.accepted;
+        self.protocol_errors += other.protocol_errors;
+        self.client_gone_before_echo += other.client_gone_before_echo;
+        self.lifetime_expired += other.lifetime_expired;
+        self.odd_wait_expired += other.odd_wait_expired;
+        self.teardown_errors += other.teardown_errors;
+        self.handed_off += other.handed_off;
+        for (bucket, count) in other.evens_before_odd {
+            *self.evens_before_odd.entry(bucket).or_insert(0) += count;
+        }
+        for (bucket, count) in other.accept_to_handle_us {
+            *self.accept_to_handle_us.entry(bucket).or_insert(0) += count;
+        }
+        for (bucket, count) in other.unsent_at_teardown {
+            *self.unsent_at_teardown.entry(bucket).or_insert(0) += count;
+        }
+        for (bucket, count) in other.unread_at_teardown {
+            *self.unread_at_teardown.entry(bucket).or_insert(0) += count;
+        }
+    }
+}
+
+/// The server's totals by teardown mode, printed when it stops.
+#[derive(Default)]
+pub struct ServerSummary {
+    modes: std::collections::BTreeMap<String, ModeSummary>,
+}
+
+#[derive(Default)]
+struct ModeSummary {
+    conns: usize,
+    /// connections that did not end `Completed`, by outcome
+    errors: std::collections::BTreeMap<String, usize>,
+    drained_bytes: u64,
+    /// nanoseconds of the final close
+    close: DeltaStats,
+}
+
+impl ServerSummary {
+    pub fn record(&mut self, report: &ConnReport, outcome: &ConnOutcome) {
+        let mode = report
+            .mode
+            .map_or_else(|| "-".to_string(), |mode| mode.to_string());
+        let summary = self.modes.entry(mode).or_default();
+        summary.conns += 1;
+        if !matches!(outcome, ConnOutcome::Completed { .. }) {
+            *summary.errors.entry(outcome.to_string()).or_insert(0) += 1;
+        }
+        summary.drained_bytes += report.timings.drained_bytes.unwrap_or(0);
+        if let Some(close) = report.timings.close {
+            summary.close.record(close.as_nanos() as i64);
+        }
+    }
+
+    /// a table with a row per teardown mode, then the errors by outcome
+    pub fn table(&self) -> String {
+        let conns: usize = self.modes.values().map(|summary| summary.conns).sum();
+        let mut text = format!("server summary, {} connections handled:\n", conns);
+        text += &format!(
+            "{:<34} {:>7} {:>7} {:>14} {:>12} {:>12}\n",
+            "mode", "conns", "errors", "drained bytes", "close p50", "close max"
+        );
+        let nanos = |ns: Option<i64>| {
+            ns.map_or_else(
+                || "-".to_string(),
+                |ns| format!("{:?}", std::time::Duration::from_nanos(ns as u64)),
+            )
+        };
+        for (mode, summary) in &self.modes {
+            let p50 = summary.close.percentiles(&[50.0]).map(|p| p[0]);
+            text += &format!(
+                "{:<34} {:>7} {:>7} {:>14} {:>12} {:>12}\n",
+                mode,
+                summary.conns,
+                summary.errors.values().sum::<usize>(),
+                summary.drained_bytes,
+                nanos(p50),
+                nanos(summary.close.max)
+            );
+        }
+        for (mode, summary) in &self.modes {
+            for (outcome, count) in &summary.errors {
+                text += &format!("{}: {} x {}\n", mode, count, outcome);
+            }
+        }
+        text
+    }
+}
+
+/// Statistics over the runs of a client.
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    /// outcome of every connection
+    pub runs: HashMap<SingleRunResult, usize>,
+    /// number of connections used by logical requests that succeeded
+    pub requests_succeeded: std::collections::BTreeMap<usize, usize>,
+    /// logical requests that failed on every connection
+    pub requests_failed: usize,
+    /// nanoseconds from the odd number's write returning to reading the response
+    pub odd_write_to_response: DeltaStats,
+    /// failed runs in order, with `--error-timeline`
+    pub errors: Vec<ErrorEvent>,
+    /// with `--passive-close`, runs that saw the server's FIN after the response
+    pub peer_fin_observed: usize,
+    /// with `--passive-close`, runs that got the response but no FIN
+    pub peer_fin_missed: usize,
+    /// with `--tls`, how the server ended the runs that got the response
+    pub tls_eofs: std::collections::BTreeMap<TlsEof, usize>,
+    /// with `--classify-teardown`, how the server ended the runs that got the
+    /// response, with nanoseconds from the response to the FIN or RST
+    pub peer_teardowns: std::collections::BTreeMap<PeerTeardown, DeltaStats>,
+    /// with `--report-socket-states`, `OURS / SERVER'S` socket state after our close
+    pub socket_states: std::collections::BTreeMap<String, usize>,
+    /// with `--report-socket-states`, TIME_WAIT sockets of ours and the
+    /// server's to each other after the runs
+    pub time_wait_after: Option<(usize, usize)>,
+    /// per-run durations
+    pub latencies: Latencies,
+    /// failed runs by the phase they first noticed the teardown in
+    pub phases: std::collections::BTreeMap<Phase, usize>,
+    /// with `--verify-sequence`, by the server's teardown mode, or `no proof`
+    /// for runs without a complete response
+    pub data_loss: std::collections::BTreeMap<String, DataLoss>,
+    /// with `--output json`, every run
+    pub run_details: Vec<serde_json::Value>,
+}
+
+/// A run that did not end with a correct response.
+#[derive(Debug)]
+pub struct ErrorEvent {
+    pub run: usize,
+    pub at: std::time::SystemTime,
+    pub result: String,
+}
+
+impl ClientStats {
+    /// add the results of `other`, e.g. a `--concurrency` thread's, numbering
+    /// its runs after ours
+    pub fn merge(&mut self, other: ClientStats) {
+        let offset = self.runs.values().sum::<usize>();
+        for (result, count) in other.runs {
+            *self.runs.entry(result).or_insert(0) += count;
+        }
+        for (conns, count) in other.requests_succeeded {
+            *self.requests_succeeded.entry(conns).or_insert(0) += count;
+        }
+        self.requests_failed += other.requests_failed;
+        self.odd_write_to_response
+            .merge(other.odd_write_to_response);
+        self.latencies.merge(other.latencies);
+        self.errors.extend(other.errors.into_iter().map(|mut e| {
+            e.run += offset;
+            e
+        }));
+        self.peer_fin_observed += other.peer_fin_observed;
+        self.peer_fin_missed += other.peer_fin_missed;
+        for (eof, count) in other.tls_eofs {
+            *self.tls_eofs.entry(eof).or_insert(0) += count;
+        }
+        for (teardown, after) in other.peer_teardowns {
+            self.peer_teardowns
+                .entry(teardown)
+                .or_default()
+                .merge(after);
+        }
+        for (states, count) in other.socket_states {
+            *self.socket_states.entry(states).or_insert(0) += count;
+        }
+        for (phase, count) in other.phases {
+            *self.phases.entry(phase).or_insert(0) += count;
+        }
+        for (key, loss) in other.data_loss {
+            self.data_loss.entry(key).or_default().merge(loss);
+        }
+        self.run_details
+            .extend(other.run_details.into_iter().map(|mut run| {
+                if let Some(index) = run["run"].as_u64() {
+                    run["run"] = (index + offset as u64).into();
+                }
+                run
+            }));
+    }
+}
+
+/// HDR histograms of per-run durations, in nanoseconds.
+#[derive(Debug)]
+pub struct Latencies {
+    connect: hdrhistogram::Histogram<u64>,
+    response: hdrhistogram::Histogram<u64>,
+    write_error: hdrhistogram::Histogram<u64>,
+    close: hdrhistogram::Histogram<u64>,
+}
+
+/// histogram of nanoseconds up to an hour with 3 significant digits
+fn nanos_histogram() -> hdrhistogram::Histogram<u64> {
+    hdrhistogram::Histogram::new_with_bounds(1, 3_600_000_000_000, 3)
+        .expect("valid histogram bounds")
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Latencies {
+            connect: nanos_histogram(),
+            response: nanos_histogram(),
+            write_error: nanos_histogram(),
+            close: nanos_histogram(),
+        }
+    }
+}
+
+impl Latencies {
+    /// the histograms with their names in output and file names
+    fn named(&self) -> [(&'static str, &hdrhistogram::Histogram<u64>); 4] {
+        [
+            ("connect", &self.connect),
+            ("response", &self.response),
+            ("write-error", &self.write_error),
+            ("close", &self.close),
+        ]
+    }
+
+    pub fn record(&mut self, record: &RunRecord) {
+        let nanos = |d: std::time::Duration| d.as_nanos().min(u128::from(u64::MAX)) as u64;
+        if !matches!(
+            record.result,
+            SingleRunResult::ConnectError(_) | SingleRunResult::ConnectTimeout
+        ) {
+            self.connect.saturating_record(nanos(record.connect));
+        }
+        if let Some(d) = record.response_after {
+            self.response.saturating_record(nanos(d));
+        }
+        if let Some(d) = record.write_error_after {
+            self.write_error.saturating_record(nanos(d));
+        }
+        if let Some(d) = record.close {
+            self.close.saturating_record(nanos(d));
+        }
+    }
+
+    fn merge(&mut self, other: Latencies) {
+        let add = |ours: &mut hdrhistogram::Histogram<u64>, theirs| {
+            ours.add(theirs).expect("histograms have the same bounds")
+        };
+        add(&mut self.connect, other.connect);
+        add(&mut self.response, other.response);
+        add(&mut self.write_error, other.write_error);
+        add(&mut self.close, other.close);
+    }
+
+    /// a line with the percentiles of each non-empty histogram
+    pub fn summary(&self) -> String {
+        let mut text = String::new();
+        for (name, h) in self.named().iter().filter(|(_, h)| !h.is_empty()) {
+            text += &format!(
+                "{} latency: p50 {}ns p90 {}ns p99 {}ns max {}ns over {} runs\n",
+                name,
+                h.value_at_quantile(0.5),
+                h.value_at_quantile(0.9),
+                h.value_at_quantile(0.99),
+                h.max(),
+                h.len()
+            );
+        }
+        text
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        self.named()
+            .iter()
+            .map(|(name, h)| {
+                let stats = if h.is_empty() {
+                    serde_json::Value::Null
+                } else {
+                    serde_json::json!({
+                        "count": h.len(),
+                        "p50_ns": h.value_at_quantile(0.5),
+                        "p90_ns": h.value_at_quantile(0.9),
+                        "p99_ns": h.value_at_quantile(0.99),
+                        "max_ns": h.max(),
+                    })
+                };
+                (name.to_string(), stats)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// write each non-empty histogram's percentile distribution in the `.hgrm`
+    /// format of HdrHistogram's `outputPercentileDistribution`
+    pub fn write_hgrm(&self, prefix: &std::path::Path) -> Result<(), failure::Error> {
+        const NS_PER_MS: f64 = 1_000_000.0;
+        for (name, h) in self.named().iter().filter(|(_, h)| !h.is_empty()) {
+            let mut path = prefix.as_os_str().to_owned();
+            path.push(format!(".{}.hgrm", name));
+            let mut text = format!(
+                "{:>12} {:>14} {:>10} {:>14}\n\n",
+                "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+            );
+            let mut total = 0;
+            for v in h.iter_quantiles(5) {
+                total += v.count_since_last_iteration();
+                let quantile = v.quantile_iterated_to();
+                let value = v.value_iterated_to() as f64 / NS_PER_MS;
+                if quantile < 1.0 {
+                    text += &format!(
+                        "{:12.6} {:2.12} {:10} {:14.2}\n",
+                        value,
+                        quantile,
+                        total,
+                        1.0 / (1.0 - quantile)
+                    );
+                } else {
+                    text += &format!("{:12.6} {:2.12} {:10}\n", value, quantile, total);
+                }
+            }
+            text += &format!(
+                "#[Mean    = {:12.6}, StdDeviation   = {:12.6}]\n",
+                h.mean() / NS_PER_MS,
+                h.stdev() / NS_PER_MS
+            );
+            text += &format!(
+                "#[Max     = {:12.6}, Total count    = {:12}]\n",
+                h.max() as f64 / NS_PER_MS,
+                h.len()
+            );
+            std::fs::write(&path, text)
+                .with_context(|_| format!("write histogram file {:?}", path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Min/mean/max and percentiles of a signed quantity in nanoseconds.
+///
+/// Min, mean and max are exact, the percentiles come from HDR histograms so
+/// memory stays bounded however many runs are recorded.
+#[derive(Debug)]
+pub struct DeltaStats {
+    pub count: u64,
+    sum: i128,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// values >= 0
+    positive: hdrhistogram::Histogram<u64>,
+    /// magnitudes of the values < 0
+    negative: hdrhistogram::Histogram<u64>,
+}
+
+impl Default for DeltaStats {
+    fn default() -> Self {
+        DeltaStats {
+            count: 0,
+            sum: 0,
+            min: None,
+            max: None,
+            positive: nanos_histogram(),
+            negative: nanos_histogram(),
+        }
+    }
+}
+
+impl DeltaStats {
+    pub fn record(&mut self, value: i64) {
+        self.count += 1;
+        self.sum += i128::from(value);
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        if value < 0 {
+            self.negative.saturating_record(value.unsigned_abs());
+        } else {
+            self.positive.saturating_record(value as u64);
+        }
+    }
+
+    fn merge(&mut self, other: DeltaStats) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+        self.positive
+            .add(other.positive)
+            .expect("histograms have the same bounds");
+        self.negative
+            .add(other.negative)
+            .expect("histograms have the same bounds");
+    }
+
+    /// Nearest-rank percentiles, `ps` in percent, to the histograms' precision.
+    pub fn percentiles(&self, ps: &[f64]) -> Option<Vec<i64>> {
+        if self.count == 0 {
+            return None;
+        }
+        let negatives = self.negative.len();
+        let positives = self.positive.len();
+        let at = |p: f64| {
+            let rank = ((p / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+            if rank <= negatives {
+                // the smallest values have the largest magnitudes
+                let q = (negatives - rank + 1) as f64 / negatives as f64;
+                -(self.negative.value_at_quantile(q) as i64)
+            } else {
+                let q = (rank - negatives) as f64 / positives as f64;
+                self.positive.value_at_quantile(q) as i64
+            }
+        };
+        // the histograms round to their buckets, the extremes are exact
+        let (min, max) = (self.min?, self.max?);
+        Some(ps.iter().map(|&p| at(p).clamp(min, max)).collect())
+    }
+
+    pub fn mean(&self) -> Option<i128> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / i128::from(self.count))
+        }
+    }
+}
+
+/// How much of the `--verify-sequence` request streams the server proved it received.
+#[derive(Debug, Default)]
+pub struct DataLoss {
+    pub runs: usize,
+    /// numbers handed to the connection's writer
+    pub sent: u64,
+    /// numbers the server proved it read
+    pub proven: u64,
+    /// `sent - proven`, the numbers that may have been lost
+    pub unproven: u64,
+}
+
+impl DataLoss {
+    pub fn record(&mut self, sent: u32, proven: u32) {
+        self.runs += 1;
+        self.sent += u64::from(sent);
+        self.proven += u64::from(proven);
+        self.unproven += u64::from(sent.saturating_sub(proven));
+    }
+
+    fn merge(&mut self, other: DataLoss) {
+        self.runs += other.runs;
+        self.sent += other.sent;
+        self.proven += other.proven;
+        self.unproven += other.unproven;
+    }
+}
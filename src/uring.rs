@@ -0,0 +1,86 @@
+//! io_uring versions of the blocking socket operations, one ring per connection
+//! and one submission at a time, so they slot into the `Read`/`Write` code paths.
+
+use io_uring::{opcode, squeue, types, IoUring};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+fn ring() -> io::Result<IoUring> {
+    IoUring::new(2)
+}
+
+/// submits `entry` and waits for its completion, returning the non-negative result
+fn run(ring: &mut IoUring, entry: squeue::Entry) -> io::Result<u32> {
+    // Safety: the buffers referenced by `entry` outlive the wait for its completion.
+    unsafe { ring.submission().push(&entry) }
+        .map_err(|_| io::Error::other("submission queue full"))?;
+    loop {
+        match ring.submit_and_wait(1) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+        if let Some(cqe) = ring.completion().next() {
+            let res = cqe.result();
+            return if res < 0 {
+                Err(io::Error::from_raw_os_error(-res))
+            } else {
+                Ok(res as u32)
+            };
+        }
+    }
+}
+
+/// A connection whose reads and writes are `IORING_OP_RECV` and `IORING_OP_SEND`.
+///
+/// A nonblocking socket still yields `WouldBlock`, wrap it in `SpinOnWouldBlock`.
+pub struct Conn<'a> {
+    ring: IoUring,
+    conn: &'a TcpStream,
+}
+
+impl<'a> Conn<'a> {
+    pub fn new(conn: &'a TcpStream) -> io::Result<Self> {
+        Ok(Conn {
+            ring: ring()?,
+            conn,
+        })
+    }
+}
+
+impl Read for Conn<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let fd = types::Fd(self.conn.as_raw_fd());
+        let entry = opcode::Recv::new(fd, buf.as_mut_ptr(), buf.len() as u32).build();
+        run(&mut self.ring, entry).map(|n| n as usize)
+    }
+}
+
+impl Write for Conn<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let fd = types::Fd(self.conn.as_raw_fd());
+        let entry = opcode::Send::new(fd, buf.as_ptr(), buf.len() as u32)
+            .flags(libc::MSG_NOSIGNAL)
+            .build();
+        run(&mut self.ring, entry).map(|n| n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// closes `conn` with `IORING_OP_CLOSE` instead of close(2)
+pub fn close(conn: TcpStream) -> io::Result<()> {
+    let fd = conn.into_raw_fd();
+    let mut ring = match ring() {
+        Ok(ring) => ring,
+        Err(e) => {
+            // don't leak the descriptor
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+    };
+    run(&mut ring, opcode::Close::new(types::Fd(fd)).build()).map(|_| ())
+}
@@ -0,0 +1,26 @@
+//! Over loopback, `close-immediately` must deliver the echo before the FIN.
+
+mod common;
+
+#[test]
+fn close_immediately_never_loses_the_echo() {
+    let times = 200;
+    let (_server, addr) = common::spawn_server(&["close-immediately"]);
+
+    // The request stream must fit into the socket buffers: if the server
+    // closes with unread data, the kernel sends an RST instead of a FIN.
+    let stats = common::run_client(
+        &addr,
+        &["--times", &times.to_string(), "--send-count", "1000"],
+    );
+    assert!(
+        !stats.contains("ReadResponseError") && !stats.contains("BothErr"),
+        "client saw read errors:\n{}",
+        stats
+    );
+    assert!(
+        stats.contains(&format!("ResponseCorrect: {},", times)),
+        "unexpected client stats:\n{}",
+        stats
+    );
+}
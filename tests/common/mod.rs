@@ -0,0 +1,58 @@
+//! Helpers to run the `tcpteardown` binary's server and client over loopback.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+pub const BIN: &str = env!("CARGO_BIN_EXE_tcpteardown");
+
+/// kills the server when the test ends, even if it fails
+pub struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// start a server on an ephemeral loopback port, returning it and its address
+///
+/// `args` follow the listen address, starting with the teardown mode.
+pub fn spawn_server(args: &[&str]) -> (KillOnDrop, String) {
+    let mut server = Command::new(BIN)
+        .args(["server", "127.0.0.1:0"])
+        .args(args)
+        .env_remove("RUST_LOG")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn server");
+    let mut lines = BufReader::new(server.stderr.take().unwrap()).lines();
+    let server = KillOnDrop(server);
+
+    // "listening on Ok(127.0.0.1:PORT) with backlog N"
+    let addr = loop {
+        let line = lines
+            .next()
+            .expect("server exited before listening")
+            .unwrap();
+        if let Some(rest) = line.split("listening on Ok(").nth(1) {
+            break rest.split(')').next().unwrap().to_string();
+        }
+    };
+    // keep draining the server's log so it never blocks on a full pipe
+    std::thread::spawn(move || lines.for_each(drop));
+    (server, addr)
+}
+
+/// run the client against `addr` and return its stdout
+pub fn run_client(addr: &str, args: &[&str]) -> String {
+    let output = Command::new(BIN)
+        .args(["client", addr])
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .expect("run client");
+    assert!(output.status.success(), "client failed: {:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
@@ -0,0 +1,84 @@
+//! The library runs the same experiments in-process, without the binary.
+
+use tcpteardown::{ClientBuilder, ServerBuilder, SingleRunResult, TeardownMode};
+
+#[test]
+fn in_process_close_immediately() {
+    let times = 50;
+    let server = ServerBuilder::new(TeardownMode::CloseImmediately)
+        .spawn()
+        .expect("spawn server");
+
+    // small enough for the socket buffers, see close_immediately.rs
+    let runs = ClientBuilder::new(server.local_addr())
+        .send_count(1000)
+        .run(times);
+    let stats = server.stop().expect("server stats");
+
+    assert_eq!(
+        runs.get(&SingleRunResult::ResponseCorrect),
+        Some(&times),
+        "unexpected client results: {:?}",
+        runs
+    );
+    assert_eq!(
+        stats.accepted, times,
+        "unexpected server stats: {:?}",
+        stats
+    );
+}
+
+#[test]
+fn client_reset_during_drain_does_not_stop_the_server() {
+    let times = 5;
+    let server = ServerBuilder::new(TeardownMode::DrainThenClose)
+        .spawn()
+        .expect("spawn server");
+
+    // linger zero: the client's close resets the connection the server drains
+    let runs = ClientBuilder::new(server.local_addr())
+        .send_count(1000)
+        .linger(Some(std::time::Duration::ZERO))
+        .run(times);
+    let stats = server.stop().expect("server stats");
+
+    assert_eq!(
+        runs.get(&SingleRunResult::ResponseCorrect),
+        Some(&times),
+        "unexpected client results: {:?}",
+        runs
+    );
+    assert_eq!(
+        stats.accepted, times,
+        "unexpected server stats: {:?}",
+        stats
+    );
+    assert_eq!(
+        stats.teardown_errors, times,
+        "unexpected server stats: {:?}",
+        stats
+    );
+}
+
+#[test]
+fn stop_reports_connections_still_running_after_grace() {
+    let server = ServerBuilder::new(TeardownMode::CloseImmediately)
+        .shutdown_grace(std::time::Duration::from_millis(100))
+        .spawn()
+        .expect("spawn server");
+
+    // never sends a number, so the server waits for it until the grace runs out
+    let stuck = std::net::TcpStream::connect(server.local_addr()).expect("connect");
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let err = server
+        .stop()
+        .expect_err("stop must fail with a connection in flight");
+
+    let label = stuck.local_addr().unwrap().to_string();
+    assert!(
+        err.to_string().contains(&label),
+        "error does not name {}: {}",
+        label,
+        err
+    );
+}
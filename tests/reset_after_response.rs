@@ -0,0 +1,37 @@
+//! Over loopback, a server that resets right after the echo (linger zero)
+//! makes a client that keeps sending see `ConnectionReset` on write.
+
+mod common;
+
+#[test]
+fn write_after_linger_zero_close_is_reset() {
+    let (_server, addr) = common::spawn_server(&["close-immediately", "--linger", "0s"]);
+
+    // The odd number goes first so the response arrives early and most of
+    // the stream is still to be written when the RST comes in.
+    let rows = common::run_client(
+        &addr,
+        &[
+            "--times",
+            "50",
+            "--send-count",
+            "100000",
+            "--odd-first",
+            "--send-after-response",
+            "--output",
+            "csv",
+        ],
+    );
+    // columns: run,result,read_error,write_error,...
+    let resets = rows
+        .lines()
+        .filter(|row| !row.starts_with('#'))
+        .map(|row| row.split(',').collect::<Vec<_>>())
+        .filter(|cols| cols[1] == "PostResponseWriteError" && cols[3] == "ConnectionReset")
+        .count();
+    assert!(
+        resets > 0,
+        "no PostResponseWriteError(ConnectionReset):\n{}",
+        rows
+    );
+}